@@ -0,0 +1,174 @@
+use nannou::color::LinSrgba;
+use nannou::image::{Rgba, RgbaImage};
+use nannou::prelude::*;
+
+use crate::config;
+
+// Entries in the table encoding tone mapped values as sRGB
+const SRGB_STEPS: usize = 4096;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    // Scaled so the densest pixel is white, the look of fractal flames
+    Log,
+    // x / (1 + x), never quite reaches white
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve, punchier than Reinhard
+    Aces,
+    // Clipped at 1 then lifted by `TONE_GAMMA`
+    Gamma,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Blend {
+    // Strokes add light on black
+    Light,
+    // Strokes soak ink into white, taking away the colours they don't have
+    Ink,
+}
+
+// Floating point stand-in for the translucent rect that fades old trails.
+// Strokes deposit energy that decays exponentially, then gets tone mapped
+// to the image shown, so trails fade smoothly all the way out.
+pub struct Accumulator {
+    blend: Blend,
+    window: Rect,
+    width: usize,
+    height: usize,
+    energy: Vec<[f32; 3]>,
+    srgb: Vec<u8>,
+    image: RgbaImage,
+    texture: wgpu::Texture,
+}
+
+impl Accumulator {
+    pub fn new(app: &App, blend: Blend) -> Accumulator {
+        let width = config::WIDTH as usize;
+        let height = config::HEIGHT as usize;
+        let texture = wgpu::TextureBuilder::new()
+            .size([config::WIDTH, config::HEIGHT])
+            .format(wgpu::TextureFormat::Rgba8UnormSrgb)
+            .usage(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING)
+            .build(app.main_window().device());
+
+        Accumulator {
+            blend,
+            window: app.window_rect(),
+            width,
+            height,
+            energy: vec![[0.; 3]; width * height],
+            srgb: (0..SRGB_STEPS)
+                .map(|i| to_srgb(i as f32 / (SRGB_STEPS - 1) as f32))
+                .collect(),
+            image: RgbaImage::new(config::WIDTH, config::HEIGHT),
+            texture,
+        }
+    }
+
+    // Once a step, before the step's strokes go in
+    pub fn decay(&mut self) {
+        for pixel in &mut self.energy {
+            *pixel = pixel.map(|e| e * config::DECAY);
+        }
+    }
+
+    // A stroke leaves `colour` times its alpha for every pixel it covers,
+    // split between the four pixels around each point so it stays smooth
+    pub fn deposit(&mut self, from: Vec2, to: Vec2, colour: LinSrgba) {
+        let length = from.distance(to);
+        if length == 0. {
+            return;
+        }
+
+        let amount = match self.blend {
+            Blend::Light => [colour.red, colour.green, colour.blue],
+            Blend::Ink => [1. - colour.red, 1. - colour.green, 1. - colour.blue],
+        }
+        .map(|c| c * colour.alpha);
+
+        let steps = length.ceil() as usize;
+        let weight = length / steps as f32;
+        for i in 0..steps {
+            let point = from.lerp(to, (i as f32 + 0.5) / steps as f32);
+            let x = point.x - self.window.left() - 0.5;
+            let y = self.window.top() - point.y - 0.5;
+            let (fx, fy) = (x - x.floor(), y - y.floor());
+            let (x, y) = (x.floor() as i64, y.floor() as i64);
+
+            for (dx, dy, share) in [
+                (0, 0, (1. - fx) * (1. - fy)),
+                (1, 0, fx * (1. - fy)),
+                (0, 1, (1. - fx) * fy),
+                (1, 1, fx * fy),
+            ] {
+                let (px, py) = (x + dx, y + dy);
+                if px < 0 || py < 0 || px >= self.width as i64 || py >= self.height as i64 {
+                    continue;
+                }
+                let pixel = &mut self.energy[py as usize * self.width + px as usize];
+                for c in 0..3 {
+                    pixel[c] += amount[c] * share * weight;
+                }
+            }
+        }
+    }
+
+    // Once a step, after the strokes, tone maps the energy into the image
+    pub fn develop(&mut self) {
+        let log_white = match config::TONE_MAP {
+            ToneMap::Log => {
+                let brightest = self
+                    .energy
+                    .iter()
+                    .flat_map(|pixel| pixel.iter())
+                    .fold(0f32, |a, &b| a.max(b));
+                (1. + brightest * config::EXPOSURE).ln().max(1e-6)
+            }
+            _ => 1.,
+        };
+
+        for (pixel, energy) in self.image.pixels_mut().zip(&self.energy) {
+            let rgb = energy.map(|e| {
+                let toned = tone(e * config::EXPOSURE, log_white).clamp(0., 1.);
+                let linear = match self.blend {
+                    Blend::Light => toned,
+                    Blend::Ink => 1. - toned,
+                };
+                self.srgb[(linear * (SRGB_STEPS - 1) as f32) as usize]
+            });
+            *pixel = Rgba([rgb[0], rgb[1], rgb[2], 255]);
+        }
+    }
+
+    pub fn draw(&self, app: &App, draw: &Draw, frame: &Frame) {
+        {
+            let window = app.main_window();
+            let mut encoder = frame.command_encoder();
+            self.texture
+                .upload_data(window.device(), &mut encoder, self.image.as_raw());
+        }
+
+        draw.texture(&self.texture)
+            .w_h(config::WIDTH as f32, config::HEIGHT as f32);
+    }
+}
+
+fn tone(x: f32, log_white: f32) -> f32 {
+    match config::TONE_MAP {
+        ToneMap::Log => (1. + x).ln() / log_white,
+        ToneMap::Reinhard => x / (1. + x),
+        ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        ToneMap::Gamma => x.min(1.).powf(1. / config::TONE_GAMMA),
+    }
+}
+
+fn to_srgb(linear: f32) -> u8 {
+    let c = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    };
+    (c.clamp(0., 1.) * 255.).round() as u8
+}
@@ -0,0 +1,99 @@
+use nannou::color::{Hsv, IntoLinSrgba, LinSrgba, Srgb};
+use nannou::image::{self, RgbImage};
+use nannou::prelude::*;
+
+use crate::colour_map::Lut;
+use crate::config;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colouring {
+    // One hue for every trail, slowly turning
+    Hue,
+    // Each stroke takes the colour of the image in the assets folder under
+    // it, the image stretched over the window
+    Image(&'static str),
+    // Each particle's own speed, heading, age, distance or noise value through
+    // the gradient in `COLOUR_MAP`
+    Map,
+}
+
+pub struct Colourer {
+    colouring: Colouring,
+    window: Rect,
+    image: Option<RgbImage>,
+    lut: Option<Lut>,
+}
+
+impl Colourer {
+    pub fn new(app: &App, colouring: Colouring) -> Colourer {
+        let image = match colouring {
+            Colouring::Image(name) => {
+                let path = app
+                    .assets_path()
+                    .expect("failed to locate `assets`")
+                    .join(name);
+                let image = image::open(&path)
+                    .unwrap_or_else(|e| panic!("failed to open source image {:?}: {}", path, e));
+                Some(image.to_rgb8())
+            }
+            _ => None,
+        };
+        let lut = match colouring {
+            Colouring::Map => Some(Lut::new(config::COLOUR_MAP)),
+            _ => None,
+        };
+
+        Colourer {
+            colouring,
+            window: app.window_rect(),
+            image,
+            lut,
+        }
+    }
+
+    // Colour of a stroke from `last_position` to `position`, `hue` turns
+    // from 0 to 1 over time. `noise` gives the field under the particle.
+    pub fn colour<F: FnOnce() -> f64>(
+        &self,
+        hue: f32,
+        last_position: Vec2,
+        position: Vec2,
+        age: u64,
+        noise: F,
+    ) -> LinSrgba {
+        let mut colour = match self.colouring {
+            Colouring::Hue => hsla(hue, 1., 0.68, 1.).into_lin_srgba(),
+            Colouring::Image(_) => self.sample(position).into_lin_srgba(),
+            Colouring::Map => {
+                let lut = self.lut.as_ref().unwrap();
+                lut.colour(last_position, position, age, noise)
+                    .into_lin_srgba()
+            }
+        };
+
+        // Slow strokes fade out, leaving the fast ones to lay down the paint
+        let speed = position.distance(last_position);
+        colour.alpha = match config::ALPHA_SPEED {
+            Some(full) => config::STROKE_ALPHA * (speed / full).min(1.),
+            None => config::STROKE_ALPHA,
+        };
+
+        colour
+    }
+
+    fn sample(&self, position: Vec2) -> Hsv {
+        let image = self.image.as_ref().unwrap();
+        let (w, h) = image.dimensions();
+        let x = (position.x - self.window.left()) / self.window.w() * w as f32;
+        let y = (self.window.top() - position.y) / self.window.h() * h as f32;
+        let pixel = image.get_pixel((x.max(0.) as u32).min(w - 1), (y.max(0.) as u32).min(h - 1));
+
+        let rgb: Srgb = Srgb::new(pixel[0], pixel[1], pixel[2]).into_format();
+        let mut hsv = Hsv::from(rgb);
+        hsv.saturation = (hsv.saturation * config::IMAGE_SATURATION).min(1.);
+        hsv.value = (hsv.value * config::IMAGE_BRIGHTNESS).min(1.);
+
+        hsv
+    }
+}
@@ -1,6 +1,66 @@
+use crate::accumulation::ToneMap;
+use crate::boundary::Boundary;
+use crate::colour_map::{ColourMap, Mapping};
+use crate::colouring::Colouring;
+use crate::flow::Flow;
+use crate::noise_field::{Basis, Layering, NoiseSettings};
+use crate::overlay::FieldView;
+use crate::spawn::Region;
+
 pub const DEBUG_LOGGING: bool = true;
 pub const CAPTURE_OUTPUT: bool = true;
 pub const PARTICLE_COUNT: i32 = 4000;
+// Seeds every particle's random numbers, the same seed gives the same run
+pub const SEED: u64 = 0;
+// None uses every core
+pub const THREAD_COUNT: Option<usize> = None;
 pub const CAPTURE_FRAMES: u64 = 4000; // 2000 frames ~= 33 seconds
 pub const HEIGHT: u32 = 960;
 pub const WIDTH: u32 = 540;
+pub const NOISE: NoiseSettings = NoiseSettings {
+    basis: Basis::Perlin,
+    layering: Layering::Single,
+    octaves: 4,
+    lacunarity: 2.,
+    persistence: 0.5,
+    seed: 0,
+    scale: (128., 137.),
+    speed: 0.01,
+};
+pub const FLOW: Flow = Flow::Noise;
+// Curl comes out a couple of times stronger than the plain lookups
+pub const CURL_STRENGTH: f32 = 0.4;
+pub const FIELD_VIEW: FieldView = FieldView::Off;
+pub const HEAT_MAP: bool = false;
+// Stops time on this frame, as if space was pressed
+pub const FREEZE_AT: Option<u64> = None;
+// Pixels between arrows, streamlines start twice as far apart
+pub const OVERLAY_SPACING: f32 = 24.;
+pub const HEAT_MAP_CELL: f32 = 8.;
+pub const BOUNDARY: Boundary = Boundary::Wrap;
+// Pixels past the edge of the window a particle can go before `BOUNDARY`
+// kicks in, negative keeps them further inside
+pub const BOUNDARY_MARGIN: f32 = 10.;
+pub const SPAWN: Region = Region::Window;
+pub const COLOURING: Colouring = Colouring::Hue;
+// Used by `Colouring::Map`, slow strokes blue and fast ones yellow
+pub const COLOUR_MAP: ColourMap = ColourMap {
+    mapping: Mapping::Speed,
+    stops: &[(0., "#1d3b8f"), (0.5, "#d94f70"), (1., "#ffe97a")],
+    range: (0., 3.),
+};
+pub const STROKE_ALPHA: f32 = 0.1;
+// Speed in pixels per frame strokes need to reach full `STROKE_ALPHA`,
+// `None` draws every stroke the same
+pub const ALPHA_SPEED: Option<f32> = None;
+// Multipliers on the saturation and brightness `Colouring::Image` samples
+pub const IMAGE_SATURATION: f32 = 1.;
+pub const IMAGE_BRIGHTNESS: f32 = 1.;
+// Fade trails in a floating point buffer instead of under a translucent rect
+pub const ACCUMULATE: bool = false;
+// Share of the buffer left after each frame
+pub const DECAY: f32 = 0.99;
+pub const TONE_MAP: ToneMap = ToneMap::Reinhard;
+pub const EXPOSURE: f32 = 1.;
+// Only used by `ToneMap::Gamma`
+pub const TONE_GAMMA: f32 = 2.2;
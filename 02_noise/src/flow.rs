@@ -0,0 +1,27 @@
+use nannou::prelude::*;
+
+use crate::config;
+use crate::noise_field::NoiseField;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    // Two separate lookups, each particle a little further along in time.
+    // Particles drift into sinks and clump together.
+    Noise,
+    // Curl of the field frozen at the first frame, particles circle along
+    // fixed streamlines
+    Curl2d,
+    // Curl of the field as it moves along its time axis, swirls that drift
+    // and turn while staying free of sinks
+    Curl3d,
+}
+
+// Push for particle `i` at `position`
+pub fn force(noise: &NoiseField, position: Vec2, frame: u64, i: usize) -> Vec2 {
+    match config::FLOW {
+        Flow::Noise => noise.vector(position, frame, i as f64 / 1000.),
+        Flow::Curl2d => noise.curl(position, 0) * config::CURL_STRENGTH,
+        Flow::Curl3d => noise.curl(position, frame) * config::CURL_STRENGTH,
+    }
+}
@@ -1,11 +1,32 @@
 use config::PARTICLE_COUNT;
+#[allow(unused_imports)]
 use log::{warn, LevelFilter};
-use nannou::noise::NoiseFn;
+use nannou::color::LinSrgba;
 use nannou::prelude::*;
+use nannou::rand::rand::rngs::SmallRng;
+use nannou::rand::rand::Rng;
+use std::time::Instant;
 
+mod accumulation;
+mod boundary;
 mod capture;
+mod colour_map;
+mod colouring;
 mod config;
+mod flow;
 mod logger;
+mod noise_field;
+mod overlay;
+mod parallel;
+mod spawn;
+
+use accumulation::{Accumulator, Blend};
+use boundary::Boundary;
+use colouring::Colourer;
+use flow::Flow;
+use noise_field::NoiseField;
+use overlay::Overlay;
+use spawn::Spawner;
 
 static LOGGER: logger::SimpleLogger = logger::SimpleLogger {
     enabled: config::DEBUG_LOGGING,
@@ -14,28 +35,44 @@ static LOGGER: logger::SimpleLogger = logger::SimpleLogger {
 fn main() {
     let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Warn));
 
-    nannou::app(model)
-        .size(config::WIDTH, config::HEIGHT)
-        .update(update)
-        .simple_window(view)
-        .run();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bench") {
+        benchmark();
+        return;
+    }
+
+    nannou::app(model).update(update).run();
 }
 
 struct Particle {
+    born: u64,
+    origin: Vec2,
     pos: Vec2,
     last_pos: Vec2,
     vel: Vec2,
+    rng: SmallRng,
 }
 
 impl Particle {
-    fn new(x: f32, y: f32) -> Particle {
+    fn new(x: f32, y: f32, born: u64, rng: SmallRng) -> Particle {
         Particle {
+            born,
+            origin: vec2(x, y),
             pos: vec2(x, y),
             last_pos: vec2(x, y),
             vel: vec2(0., 0.),
+            rng,
         }
     }
 
+    // Back to where it first appeared
+    fn reset(&mut self, frame: u64) {
+        self.born = frame;
+        self.pos = self.origin;
+        self.last_pos = self.origin;
+        self.vel = vec2(0., 0.);
+    }
+
     fn update(&mut self, dir: Vec2) {
         self.last_pos = self.pos;
         self.pos += self.vel;
@@ -46,91 +83,222 @@ impl Particle {
 
 struct Model {
     particles: Vec<Particle>,
+    noise: NoiseField,
+    overlay: Overlay,
+    spawner: Spawner,
+    colourer: Colourer,
+    accumulator: Option<Accumulator>,
+    frame: u64,
     color_angle: f32,
 }
 
 fn model(app: &App) -> Model {
-    let window = app.window_rect();
-    let r = window.right();
-    let l = window.left();
-
-    let w = l - r;
-    let t = window.top();
-    let b = window.bottom();
-
-    let h = t - b;
-
-    // warn!("{} {}", w, window.w());
+    app.new_window()
+        .size(config::WIDTH, config::HEIGHT)
+        .view(view)
+        .key_pressed(key_pressed)
+        .build()
+        .unwrap();
 
-    let mut p = vec![];
-    for _i in 0..PARTICLE_COUNT {
-        let x = random_f32() * w + r;
-        let y = random_f32() * h + b;
-        p.push(Particle::new(x, y));
-    }
+    let mut spawner = Spawner::new(app.window_rect(), config::SPAWN);
 
     Model {
-        particles: p,
+        particles: spawn(&mut spawner, PARTICLE_COUNT as usize),
+        noise: NoiseField::new(&config::NOISE),
+        overlay: Overlay::new(),
+        spawner,
+        colourer: Colourer::new(app, config::COLOURING),
+        accumulator: config::ACCUMULATE.then(|| Accumulator::new(app, Blend::Light)),
+        frame: 0,
         color_angle: 0.575,
     }
 }
 
 fn update(app: &App, model: &mut Model, _update: Update) {
-    let noise = nannou::noise::Perlin::new();
-    let elapsed_frames = app.elapsed_frames() as f64;
-    let t = elapsed_frames / 100.;
-    for i in 0..model.particles.len() {
-        let p = &mut model.particles[i];
-        let mut x = noise.get([
-            p.pos.x as f64 / 128.,
-            p.pos.y as f64 / 137.,
-            t + i as f64 / 1000.,
-        ]) as f32;
-        let mut y = noise.get([
-            -p.pos.y as f64 / 128.,
-            p.pos.x as f64 / 137.,
-            t + i as f64 / 1000.,
-        ]) as f32;
-
-        if elapsed_frames % 20. == 0. {
+    let window = app.window_rect();
+    let frame = model.overlay.tick(app.elapsed_frames());
+    if model.overlay.frozen {
+        return;
+    }
+    model.frame = frame;
+
+    step(
+        &mut model.particles,
+        &model.noise,
+        &mut model.spawner,
+        window,
+        frame,
+        parallel::thread_count(),
+    );
+
+    model.color_angle += 0.001;
+    model.color_angle %= 1.0;
+
+    if config::ACCUMULATE {
+        let strokes: Vec<_> = model
+            .particles
+            .iter()
+            .map(|p| (p.last_pos, p.pos, stroke(model, p)))
+            .collect();
+        let accumulator = model.accumulator.as_mut().unwrap();
+        accumulator.decay();
+        for (from, to, colour) in strokes {
+            accumulator.deposit(from, to, colour);
+        }
+        accumulator.develop();
+    }
+}
+
+fn spawn(spawner: &mut Spawner, count: usize) -> Vec<Particle> {
+    (0..count)
+        .map(|i| {
+            let mut rng = parallel::stream(i);
+            let position = spawner.position(&mut rng);
+            Particle::new(position.x, position.y, 0, rng)
+        })
+        .collect()
+}
+
+// Moves every particle one frame, spread over `threads` cores
+fn step(
+    particles: &mut [Particle],
+    noise: &NoiseField,
+    spawner: &mut Spawner,
+    window: Rect,
+    frame: u64,
+    threads: usize,
+) {
+    parallel::for_each(particles, threads, |i, p| {
+        let force = flow::force(noise, p.pos, frame, i);
+        let (mut x, mut y) = (force.x, force.y);
+
+        // Kicks would push curl flows off their streamlines
+        if config::FLOW == Flow::Noise && frame.is_multiple_of(20) {
             if x < 0. {
-                x -= random_f32();
+                x -= p.rng.gen::<f32>();
             } else {
-                x += random_f32();
+                x += p.rng.gen::<f32>();
             }
 
             if y < 0. {
-                y -= random_f32();
+                y -= p.rng.gen::<f32>();
             } else {
-                y += random_f32();
+                y += p.rng.gen::<f32>();
             }
         }
 
         let dir = vec2(x, y);
         p.update(dir);
+    });
+
+    // In order, one thread, the spawner is shared
+    for p in particles.iter_mut() {
+        if boundary::is_outside(window, p.pos) {
+            match config::BOUNDARY {
+                Boundary::Wrap => boundary::wrap(window, &mut p.pos, &mut p.last_pos),
+                Boundary::Bounce => boundary::bounce(window, &mut p.pos, &mut p.vel),
+                Boundary::Respawn => p.reset(frame),
+                Boundary::Kill => {
+                    let mut rng = p.rng.clone();
+                    let position = spawner.position(&mut rng);
+                    *p = Particle::new(position.x, position.y, frame, rng);
+                }
+            }
+        }
     }
+}
 
-    model.color_angle += 0.001;
-    model.color_angle = model.color_angle % 1.0;
+// `cargo run --release -- bench`, times a few seconds of steps at each
+// particle count without opening a window
+fn benchmark() {
+    const COUNTS: [usize; 3] = [10_000, 100_000, 1_000_000];
+    const SECONDS: f32 = 3.;
+
+    let window = Rect::from_w_h(config::WIDTH as f32, config::HEIGHT as f32);
+    let noise = NoiseField::new(&config::NOISE);
+    let threads = parallel::thread_count();
+    warn!("Benchmark on {} threads", threads);
+
+    for count in COUNTS {
+        let mut spawner = Spawner::new(window, config::SPAWN);
+        let mut particles = spawn(&mut spawner, count);
+
+        let start = Instant::now();
+        let mut frame = 0;
+        while frame < 3 || start.elapsed().as_secs_f32() < SECONDS {
+            step(&mut particles, &noise, &mut spawner, window, frame, threads);
+            frame += 1;
+        }
+        let steps = frame as f32 / start.elapsed().as_secs_f32();
+        warn!("{:>9} particles: {:.1} steps/s", count, steps);
+    }
+}
+
+fn stroke(model: &Model, p: &Particle) -> LinSrgba {
+    model.colourer.colour(
+        model.color_angle,
+        p.last_pos,
+        p.pos,
+        model.frame.saturating_sub(p.born),
+        || model.noise.get(p.pos, model.frame, 0.),
+    )
+}
+
+fn key_pressed(_app: &App, model: &mut Model, key: Key) {
+    model.overlay.key_pressed(key);
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
     let draw = app.draw();
-    if frame.nth() == 0 {
-        frame.clear(BLACK);
+    if let Some(accumulator) = &model.accumulator {
+        accumulator.draw(app, &draw, &frame);
     } else {
-        draw.rect()
-            .w_h(config::WIDTH as f32, config::HEIGHT as f32)
-            .x_y(0., 0.)
-            .color(rgba(0., 0., 0., 0.01));
-    }
+        // Trails would smear the overlay
+        if frame.nth() == 0 || model.overlay.is_visible() {
+            frame.clear(BLACK);
+        } else {
+            draw.rect()
+                .w_h(config::WIDTH as f32, config::HEIGHT as f32)
+                .x_y(0., 0.)
+                .color(rgba(0., 0., 0., 0.01));
+        }
 
-    for p in &model.particles {
-        draw.line()
-            .start(p.last_pos)
-            .end(p.pos)
-            .color(hsla(model.color_angle, 1., 0.68, 0.1));
+        for p in &model.particles {
+            draw.line()
+                .start(p.last_pos)
+                .end(p.pos)
+                .color(stroke(model, p));
+        }
     }
+
+    model
+        .overlay
+        .draw(&draw, app.window_rect(), &model.noise, |position, frame| {
+            // Particle 0's time slice of `Flow::Noise`
+            flow::force(&model.noise, position, frame, 0)
+        });
     draw.to_frame(app, &frame).unwrap();
     capture::capture(app, frame)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_come_out_the_same_on_any_number_of_threads() {
+        let window = Rect::from_w_h(config::WIDTH as f32, config::HEIGHT as f32);
+        let noise = NoiseField::new(&config::NOISE);
+        let run = |threads| {
+            let mut spawner = Spawner::new(window, config::SPAWN);
+            // Enough particles that every thread gets some
+            let mut particles = spawn(&mut spawner, 4 * parallel::MIN_CHUNK);
+            for frame in 0..30 {
+                step(&mut particles, &noise, &mut spawner, window, frame, threads);
+            }
+            particles.iter().map(|p| p.pos).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(1), run(4));
+    }
+}
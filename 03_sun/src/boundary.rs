@@ -0,0 +1,59 @@
+use nannou::prelude::*;
+
+use crate::config;
+
+// What happens to a particle that leaves the window
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boundary {
+    // Comes back in on the opposite side
+    Wrap,
+    // Reflects off the edge
+    Bounce,
+    // Starts over from the sketch's emitter
+    Respawn,
+    // Replaced by a brand new particle, as if the sketch had just started
+    Kill,
+}
+
+// The window grown by `BOUNDARY_MARGIN` on every side
+fn bounds(window: Rect) -> Rect {
+    window.pad(-config::BOUNDARY_MARGIN)
+}
+
+pub fn is_outside(window: Rect, position: Vec2) -> bool {
+    let bounds = bounds(window);
+    position.x < bounds.left()
+        || position.x > bounds.right()
+        || position.y < bounds.bottom()
+        || position.y > bounds.top()
+}
+
+// Moves the particle across to the opposite edge. `last_position` moves with
+// it so no line gets drawn across the window.
+pub fn wrap(window: Rect, position: &mut Vec2, last_position: &mut Vec2) {
+    let bounds = bounds(window);
+    let wrapped = vec2(
+        (position.x - bounds.left()).rem_euclid(bounds.w()) + bounds.left(),
+        (position.y - bounds.bottom()).rem_euclid(bounds.h()) + bounds.bottom(),
+    );
+
+    *last_position += wrapped - *position;
+    *position = wrapped;
+}
+
+// Mirrors the particle back inside and turns its velocity around
+pub fn bounce(window: Rect, position: &mut Vec2, velocity: &mut Vec2) {
+    let bounds = bounds(window);
+
+    if position.x < bounds.left() || position.x > bounds.right() {
+        let edge = position.x.clamp(bounds.left(), bounds.right());
+        position.x = (2. * edge - position.x).clamp(bounds.left(), bounds.right());
+        velocity.x = -velocity.x;
+    }
+    if position.y < bounds.bottom() || position.y > bounds.top() {
+        let edge = position.y.clamp(bounds.bottom(), bounds.top());
+        position.y = (2. * edge - position.y).clamp(bounds.bottom(), bounds.top());
+        velocity.y = -velocity.y;
+    }
+}
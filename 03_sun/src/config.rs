@@ -1,7 +1,50 @@
+use crate::accumulation::ToneMap;
+use crate::boundary::Boundary;
+use crate::colour_map::{ColourMap, Mapping};
+use crate::spawn::Region;
+
 pub const DEBUG_LOGGING: bool = true;
 pub const CAPTURE_OUTPUT: bool = true;
 pub const PARTICLE_COUNT: i32 = 8000;
+// Seeds every particle's random numbers, the same seed gives the same run
+pub const SEED: u64 = 0;
+// None uses every core
+pub const THREAD_COUNT: Option<usize> = None;
 pub const CAPTURE_FRAMES: u64 = 2000; // 2000 frames ~= 33 seconds
 pub const HEIGHT: u32 = 960;
 pub const WIDTH: u32 = 540;
 pub const RADIUS: f32 = 70.;
+// Particles spiral ever outwards, so with `Wrap` or `Bounce` the ones that
+// reach an edge stay along the edges
+pub const BOUNDARY: Boundary = Boundary::Respawn;
+// Pixels past the edge of the window a particle can go before `BOUNDARY`
+// kicks in, negative keeps them further inside
+pub const BOUNDARY_MARGIN: f32 = 10.;
+// Equal radii, on the circle of `RADIUS`
+pub const SPAWN: Region = Region::Annulus {
+    x: 0.,
+    y: 0.,
+    inner: RADIUS,
+    outer: RADIUS,
+};
+// Colours strokes by speed, heading, age or distance, `None` keeps them flat
+// black. There's no noise field here to colour by.
+pub const COLOUR_MAP: Option<ColourMap> = None;
+const _: () = assert!(
+    !matches!(
+        COLOUR_MAP,
+        Some(ColourMap {
+            mapping: Mapping::Noise,
+            ..
+        })
+    ),
+    "03_sun has no noise field to colour by"
+);
+// Fade trails in a floating point buffer instead of under a translucent rect
+pub const ACCUMULATE: bool = false;
+// Share of the buffer left after each frame
+pub const DECAY: f32 = 0.9;
+pub const TONE_MAP: ToneMap = ToneMap::Reinhard;
+pub const EXPOSURE: f32 = 1.;
+// Only used by `ToneMap::Gamma`
+pub const TONE_GAMMA: f32 = 2.2;
@@ -1,11 +1,25 @@
 use config::PARTICLE_COUNT;
+#[allow(unused_imports)]
 use log::{warn, LevelFilter};
-use nannou::noise::NoiseFn;
+use nannou::color::{IntoLinSrgba, LinSrgba};
 use nannou::prelude::*;
+use nannou::rand::rand::rngs::SmallRng;
+use nannou::rand::rand::Rng;
+use std::time::Instant;
 
+mod accumulation;
+mod boundary;
 mod capture;
+mod colour_map;
 mod config;
 mod logger;
+mod parallel;
+mod spawn;
+
+use accumulation::{Accumulator, Blend};
+use boundary::Boundary;
+use colour_map::Lut;
+use spawn::Spawner;
 
 static LOGGER: logger::SimpleLogger = logger::SimpleLogger {
     enabled: config::DEBUG_LOGGING,
@@ -14,6 +28,12 @@ static LOGGER: logger::SimpleLogger = logger::SimpleLogger {
 fn main() {
     let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Warn));
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bench") {
+        benchmark();
+        return;
+    }
+
     nannou::app(model)
         .size(config::WIDTH, config::HEIGHT)
         .update(update)
@@ -22,21 +42,28 @@ fn main() {
 }
 
 struct Particle {
+    born: u64,
     pos: Vec2,
     last_pos: Vec2,
     vel: Vec2,
     exit_frame: u64,
     angle: f32,
+    // 1 or -1, flipped by bouncing off an edge
+    turn: f32,
+    rng: SmallRng,
 }
 
 impl Particle {
-    fn new(x: f32, y: f32, angle: f32) -> Particle {
+    fn new(x: f32, y: f32, angle: f32, base_frame: u64, mut rng: SmallRng) -> Particle {
         Particle {
+            born: base_frame,
             pos: vec2(x, y),
             last_pos: vec2(x, y),
             vel: vec2(0., 0.),
-            exit_frame: (random_f32() * 500.) as u64,
+            exit_frame: (rng.gen::<f32>() * 500.) as u64 + base_frame,
             angle,
+            turn: 1.,
+            rng,
         }
     }
 
@@ -53,9 +80,9 @@ impl Particle {
         }
 
         if limit % 2 == 0 {
-            self.angle -= 0.005;
+            self.angle -= 0.005 * self.turn;
         } else {
-            self.angle += 0.005;
+            self.angle += 0.005 * self.turn;
         }
 
         let x = r * self.angle.cos();
@@ -66,78 +93,203 @@ impl Particle {
         self.vel *= 0.005;
     }
 
-    fn reset(&mut self, base_frame: u64) {
-        let theta = random_f32() * 2. * PI;
-
-        let x = config::RADIUS * theta.cos();
-        let y = config::RADIUS * theta.sin();
-
-        self.pos = vec2(x, y);
-        self.last_pos = vec2(x, y);
-        self.angle = theta;
+    fn reset(&mut self, position: Vec2, base_frame: u64) {
+        self.born = base_frame;
+        self.pos = position;
+        self.last_pos = position;
+        self.angle = position.y.atan2(position.x);
+        self.turn = 1.;
         self.vel = vec2(0., 0.);
-        self.exit_frame = (random_f32() * 500.) as u64 + base_frame;
+        self.exit_frame = (self.rng.gen::<f32>() * 500.) as u64 + base_frame;
     }
 }
 
 struct Model {
     particles: Vec<Particle>,
+    spawner: Spawner,
+    lut: Option<Lut>,
+    accumulator: Option<Accumulator>,
 }
 
-fn model(_app: &App) -> Model {
-    let mut p = vec![];
-    for _i in 0..PARTICLE_COUNT {
-        let theta = random_f32() * 2. * PI;
+fn model(app: &App) -> Model {
+    let mut spawner = Spawner::new(app.window_rect(), config::SPAWN);
+    let particles = (0..PARTICLE_COUNT as usize)
+        .map(|i| spawn(&mut spawner, parallel::stream(i), 0))
+        .collect();
 
-        let x = config::RADIUS * theta.cos();
-        let y = config::RADIUS * theta.sin();
-        p.push(Particle::new(x, y, theta));
+    Model {
+        particles,
+        spawner,
+        lut: config::COLOUR_MAP.map(Lut::new),
+        accumulator: config::ACCUMULATE.then(|| Accumulator::new(app, Blend::Ink)),
     }
+}
 
-    Model { particles: p }
+// Particles circle the centre, starting at the angle they spawn at
+fn spawn(spawner: &mut Spawner, mut rng: SmallRng, base_frame: u64) -> Particle {
+    let position = spawner.position(&mut rng);
+    let angle = position.y.atan2(position.x);
+    Particle::new(position.x, position.y, angle, base_frame, rng)
 }
 
-fn is_out_of_frame(frame: Rect, pos: Vec2) -> bool {
-    (pos.x < frame.left() || pos.x > frame.right())
-        && (pos.y < frame.bottom() || pos.y > frame.top())
+// Moves every particle one frame, spread over `threads` cores
+fn step(
+    particles: &mut [Particle],
+    spawner: &mut Spawner,
+    window: Rect,
+    frame: u64,
+    threads: usize,
+) {
+    let outside: Vec<usize> = (0..particles.len())
+        .filter(|&i| boundary::is_outside(window, particles[i].pos))
+        .collect();
+
+    parallel::for_each(particles, threads, |_, p| {
+        if !boundary::is_outside(window, p.pos) && frame >= p.exit_frame {
+            p.update(frame);
+        }
+    });
+
+    // In order, one thread, the spawner is shared
+    for i in outside {
+        let p = &mut particles[i];
+        match config::BOUNDARY {
+            // `update` rebuilds the position from the angle, so it has to
+            // follow the particle to where it ended up
+            Boundary::Wrap => {
+                boundary::wrap(window, &mut p.pos, &mut p.last_pos);
+                p.angle = p.pos.y.atan2(p.pos.x);
+            }
+            Boundary::Bounce => {
+                boundary::bounce(window, &mut p.pos, &mut p.vel);
+                p.angle = p.pos.y.atan2(p.pos.x);
+                p.turn = -p.turn;
+            }
+            Boundary::Respawn => {
+                let position = spawner.position(&mut p.rng);
+                p.reset(position, frame);
+            }
+            Boundary::Kill => *p = spawn(spawner, p.rng.clone(), frame),
+        }
+    }
+}
+
+// `cargo run --release -- bench`, times a few seconds of steps at each
+// particle count without opening a window
+fn benchmark() {
+    const COUNTS: [usize; 3] = [10_000, 100_000, 1_000_000];
+    const SECONDS: f32 = 3.;
+
+    let window = Rect::from_w_h(config::WIDTH as f32, config::HEIGHT as f32);
+    let threads = parallel::thread_count();
+    warn!("Benchmark on {} threads", threads);
+
+    for count in COUNTS {
+        let mut spawner = Spawner::new(window, config::SPAWN);
+        let mut particles: Vec<Particle> = (0..count)
+            .map(|i| spawn(&mut spawner, parallel::stream(i), 0))
+            .collect();
+
+        let start = Instant::now();
+        let mut frame = 0;
+        while frame < 3 || start.elapsed().as_secs_f32() < SECONDS {
+            step(&mut particles, &mut spawner, window, frame, threads);
+            frame += 1;
+        }
+        let steps = frame as f32 / start.elapsed().as_secs_f32();
+        warn!("{:>9} particles: {:.1} steps/s", count, steps);
+    }
 }
 
 fn update(app: &App, model: &mut Model, _update: Update) {
     let window = app.window_rect();
     let frame = app.elapsed_frames();
 
-    for i in 0..model.particles.len() {
-        let p = &mut model.particles[i];
+    step(
+        &mut model.particles,
+        &mut model.spawner,
+        window,
+        frame,
+        parallel::thread_count(),
+    );
 
-        if is_out_of_frame(window, p.pos) {
-            p.reset(frame);
-        } else if frame >= p.exit_frame {
-            p.update(frame);
+    if config::ACCUMULATE {
+        let strokes: Vec<_> = model
+            .particles
+            .iter()
+            .map(|p| (p.last_pos, p.pos, stroke(model, p, frame)))
+            .collect();
+        let accumulator = model.accumulator.as_mut().unwrap();
+        accumulator.decay();
+        for (from, to, colour) in strokes {
+            accumulator.deposit(from, to, colour);
         }
+        accumulator.develop();
     }
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
     let draw = app.draw();
 
-    if frame.nth() == 0 {
-        frame.clear(WHITE)
-    }
+    if let Some(accumulator) = &model.accumulator {
+        accumulator.draw(app, &draw, &frame);
+    } else {
+        if frame.nth() == 0 {
+            frame.clear(WHITE)
+        }
+
+        // draw.background().color(WHITE);
+        draw.rect()
+            .w_h(config::WIDTH as f32, config::HEIGHT as f32)
+            .x_y(0., 0.)
+            .color(rgba(1., 1., 1., 0.1));
 
-    // draw.background().color(WHITE);
-    draw.rect()
-        .w_h(config::WIDTH as f32, config::HEIGHT as f32)
-        .x_y(0., 0.)
-        .color(rgba(1., 1., 1., 0.1));
-
-    for p in &model.particles {
-        draw.line()
-            .start(p.last_pos)
-            .end(p.pos)
-            // .weight(4.)
-            .color(hsla(0., 0., 0., 0.5));
+        for p in &model.particles {
+            draw.line()
+                .start(p.last_pos)
+                .end(p.pos)
+                // .weight(4.)
+                .color(stroke(model, p, frame.nth()));
+        }
     }
 
     draw.to_frame(app, &frame).unwrap();
     capture::capture(app, frame)
 }
+
+fn stroke(model: &Model, p: &Particle, frame: u64) -> LinSrgba {
+    match &model.lut {
+        Some(lut) => {
+            let age = frame.saturating_sub(p.born);
+            // Noise is ruled out in config.rs, so it's never asked for
+            let mut colour = lut.colour(p.last_pos, p.pos, age, || 0.).into_lin_srgba();
+            colour.alpha = 0.5;
+            colour
+        }
+        None => hsla(0., 0., 0., 0.5).into_lin_srgba(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_come_out_the_same_on_any_number_of_threads() {
+        let window = Rect::from_w_h(config::WIDTH as f32, config::HEIGHT as f32);
+        let run = |threads| {
+            let mut spawner = Spawner::new(window, config::SPAWN);
+            // Enough particles that every thread gets some
+            let mut particles: Vec<Particle> = (0..4 * parallel::MIN_CHUNK)
+                .map(|i| spawn(&mut spawner, parallel::stream(i), 0))
+                .collect();
+            // Particles start moving somewhere in the first 500 frames
+            for frame in 0..600 {
+                step(&mut particles, &mut spawner, window, frame, threads);
+            }
+            particles.iter().map(|p| p.pos).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(1), run(4));
+    }
+}
@@ -0,0 +1,49 @@
+use nannou::rand::rand::rngs::SmallRng;
+use nannou::rand::rand::SeedableRng;
+
+use crate::config;
+
+// Fewer particles than this per thread aren't worth the threads
+pub const MIN_CHUNK: usize = 2048;
+
+pub fn thread_count() -> usize {
+    config::THREAD_COUNT.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    })
+}
+
+// The random numbers of particle `index`. Every particle draws from its own
+// stream, so a run comes out the same whichever thread moves it.
+pub fn stream(index: usize) -> SmallRng {
+    SmallRng::seed_from_u64(config::SEED.wrapping_add(index as u64))
+}
+
+// Runs `job` on every item with its index, the slice cut into one run of
+// neighbours for each of up to `threads` threads
+pub fn for_each<T, F>(items: &mut [T], threads: usize, job: F)
+where
+    T: Send,
+    F: Fn(usize, &mut T) + Sync,
+{
+    let threads = threads.min(items.len() / MIN_CHUNK).max(1);
+    if threads == 1 {
+        for (i, item) in items.iter_mut().enumerate() {
+            job(i, item);
+        }
+        return;
+    }
+
+    let chunk = items.len().div_ceil(threads);
+    let job = &job;
+    std::thread::scope(|scope| {
+        for (c, items) in items.chunks_mut(chunk).enumerate() {
+            scope.spawn(move || {
+                for (i, item) in items.iter_mut().enumerate() {
+                    job(c * chunk + i, item);
+                }
+            });
+        }
+    });
+}
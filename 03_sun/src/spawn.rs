@@ -0,0 +1,330 @@
+use nannou::image;
+use nannou::prelude::*;
+use nannou::rand::rand::rngs::SmallRng;
+use nannou::rand::rand::seq::SliceRandom;
+use nannou::rand::rand::{Rng, SeedableRng};
+
+use crate::config;
+
+// Where new particles appear, in window coordinates
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum Region {
+    Window,
+    Rect {
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+    },
+    Circle {
+        x: f32,
+        y: f32,
+        radius: f32,
+    },
+    // Between the two radii, equal radii spawn on the circle itself
+    Annulus {
+        x: f32,
+        y: f32,
+        inner: f32,
+        outer: f32,
+    },
+    Polygon(&'static [(f32, f32)]),
+    // Spread over the window at least `spacing` apart, none of the clumps and
+    // gaps uniform sampling leaves
+    PoissonDisc {
+        spacing: f32,
+    },
+    // Image in the assets folder stretched over the window, brighter pixels
+    // are more likely to spawn a particle and black ones never do
+    Mask(&'static str),
+}
+
+pub struct Spawner {
+    region: Region,
+    window: Rect,
+    // Running total of brightness over the mask's pixels
+    mask: Vec<f64>,
+    mask_size: (u32, u32),
+    // Poisson disc points, shuffled, handed out in turn
+    points: Vec<Vec2>,
+    next: usize,
+}
+
+impl Spawner {
+    // Takes the window rather than the app so it works without one too
+    pub fn new(window: Rect, region: Region) -> Spawner {
+        let mut spawner = Spawner {
+            region,
+            window,
+            mask: vec![],
+            mask_size: (0, 0),
+            points: vec![],
+            next: 0,
+        };
+
+        match region {
+            Region::Polygon(points) if points.len() < 3 => {
+                panic!("spawn polygon needs at least 3 points")
+            }
+            Region::PoissonDisc { spacing } => {
+                let mut rng = SmallRng::seed_from_u64(config::SEED);
+                spawner.points = poisson_disc(window, spacing, &mut rng);
+                spawner.points.shuffle(&mut rng);
+            }
+            Region::Mask(name) => {
+                let path = nannou::app::find_assets_path()
+                    .expect("failed to locate `assets`")
+                    .join(name);
+                let image = image::open(&path)
+                    .unwrap_or_else(|e| panic!("failed to open spawn mask {:?}: {}", path, e))
+                    .to_luma8();
+                spawner.mask = running_total(&image);
+                if spawner.mask.last().is_none_or(|&total| total == 0.) {
+                    panic!("spawn mask {:?} is black all over", path);
+                }
+                spawner.mask_size = image.dimensions();
+            }
+            _ => {}
+        }
+
+        spawner
+    }
+
+    // Draws from the particle's own `rng`
+    pub fn position(&mut self, rng: &mut SmallRng) -> Vec2 {
+        let window = self.window;
+        match self.region {
+            Region::Window => vec2(
+                rng.gen_range(window.left()..window.right()),
+                rng.gen_range(window.bottom()..window.top()),
+            ),
+            Region::Rect { x, y, w, h } => vec2(
+                x + (rng.gen::<f32>() - 0.5) * w,
+                y + (rng.gen::<f32>() - 0.5) * h,
+            ),
+            Region::Circle { x, y, radius } => {
+                // Square root so the middle doesn't get more than its share
+                let r = radius * rng.gen::<f32>().sqrt();
+                vec2(x, y) + angle_vector(rng.gen::<f32>() * 2. * PI) * r
+            }
+            Region::Annulus { x, y, inner, outer } => {
+                let r = (inner * inner + rng.gen::<f32>() * (outer * outer - inner * inner)).sqrt();
+                vec2(x, y) + angle_vector(rng.gen::<f32>() * 2. * PI) * r
+            }
+            Region::Polygon(points) => {
+                let (mut low, mut high) = (vec2(f32::MAX, f32::MAX), vec2(f32::MIN, f32::MIN));
+                for &(x, y) in points {
+                    low = low.min(vec2(x, y));
+                    high = high.max(vec2(x, y));
+                }
+                for _ in 0..10000 {
+                    let p = vec2(rng.gen_range(low.x..high.x), rng.gen_range(low.y..high.y));
+                    if contains(points, p) {
+                        return p;
+                    }
+                }
+                panic!("spawn polygon has no area")
+            }
+            Region::PoissonDisc { .. } => {
+                let p = self.points[self.next];
+                self.next = (self.next + 1) % self.points.len();
+                p
+            }
+            Region::Mask(_) => {
+                let target = rng.gen::<f64>() * self.mask.last().unwrap();
+                let index = self.mask.partition_point(|&total| total <= target);
+                let (w, h) = self.mask_size;
+                let column = (index as u32 % w) as f32 + rng.gen::<f32>();
+                let row = (index as u32 / w) as f32 + rng.gen::<f32>();
+                vec2(
+                    window.left() + column / w as f32 * window.w(),
+                    window.top() - row / h as f32 * window.h(),
+                )
+            }
+        }
+    }
+}
+
+fn running_total(image: &image::GrayImage) -> Vec<f64> {
+    let mut total = 0.;
+    image
+        .pixels()
+        .map(|pixel| {
+            total += pixel[0] as f64;
+            total
+        })
+        .collect()
+}
+
+fn angle_vector(angle: f32) -> Vec2 {
+    vec2(angle.cos(), angle.sin())
+}
+
+// Even-odd rule, counts the edges a ray going right from `p` crosses
+fn contains(points: &[(f32, f32)], p: Vec2) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (xi, yi) = points[i];
+        let (xj, yj) = points[j];
+        if (yi > p.y) != (yj > p.y) && p.x < xj + (p.y - yj) / (yi - yj) * (xi - xj) {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
+// Bridson's algorithm, grows outwards from a random point trying 30
+// candidates around each point until there's no room left
+fn poisson_disc(window: Rect, spacing: f32, rng: &mut SmallRng) -> Vec<Vec2> {
+    const CANDIDATES: usize = 30;
+
+    let cell = spacing / 2f32.sqrt();
+    let columns = (window.w() / cell).ceil() as usize;
+    let rows = (window.h() / cell).ceil() as usize;
+    let cell_of = |p: Vec2| {
+        (
+            (((p.x - window.left()) / cell) as usize).min(columns - 1),
+            (((p.y - window.bottom()) / cell) as usize).min(rows - 1),
+        )
+    };
+
+    // Index into `points` of the one point each cell can hold
+    let mut grid: Vec<Option<usize>> = vec![None; columns * rows];
+    let mut points = vec![];
+    let mut active = vec![];
+
+    let first = vec2(
+        rng.gen_range(window.left()..window.right()),
+        rng.gen_range(window.bottom()..window.top()),
+    );
+    let (column, row) = cell_of(first);
+    grid[row * columns + column] = Some(0);
+    points.push(first);
+    active.push(0);
+
+    while !active.is_empty() {
+        let slot = rng.gen_range(0..active.len());
+        let centre = points[active[slot]];
+
+        let found = (0..CANDIDATES)
+            .map(|_| {
+                let r = spacing * (1. + rng.gen::<f32>());
+                centre + angle_vector(rng.gen::<f32>() * 2. * PI) * r
+            })
+            .find(|&p| {
+                if !window.contains(p) {
+                    return false;
+                }
+                let (column, row) = cell_of(p);
+                (row.saturating_sub(2)..(row + 3).min(rows)).all(|r| {
+                    (column.saturating_sub(2)..(column + 3).min(columns)).all(|c| {
+                        grid[r * columns + c].is_none_or(|i| points[i].distance(p) >= spacing)
+                    })
+                })
+            });
+
+        match found {
+            Some(p) => {
+                let (column, row) = cell_of(p);
+                grid[row * columns + column] = Some(points.len());
+                active.push(points.len());
+                points.push(p);
+            }
+            None => {
+                active.swap_remove(slot);
+            }
+        }
+    }
+
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRAWS: usize = 2000;
+
+    fn window() -> Rect {
+        Rect::from_w_h(400., 300.)
+    }
+
+    fn positions(spawner: &mut Spawner) -> Vec<Vec2> {
+        let mut rng = SmallRng::seed_from_u64(1);
+        (0..DRAWS).map(|_| spawner.position(&mut rng)).collect()
+    }
+
+    #[test]
+    fn polygon_points_land_inside_it() {
+        // An L, the notch at the top right is inside its bounding box
+        const L: &[(f32, f32)] = &[
+            (-100., -100.),
+            (100., -100.),
+            (100., 0.),
+            (0., 0.),
+            (0., 100.),
+            (-100., 100.),
+        ];
+        let mut spawner = Spawner::new(window(), Region::Polygon(L));
+        for p in positions(&mut spawner) {
+            assert!(p.x.abs() <= 100. && p.y.abs() <= 100., "{p:?}");
+            assert!(p.x <= 0. || p.y <= 0., "{p:?} is in the notch");
+        }
+    }
+
+    #[test]
+    fn annulus_points_land_between_the_radii() {
+        let region = Region::Annulus {
+            x: 20.,
+            y: -10.,
+            inner: 50.,
+            outer: 80.,
+        };
+        let mut spawner = Spawner::new(window(), region);
+        for p in positions(&mut spawner) {
+            let r = p.distance(vec2(20., -10.));
+            assert!((50. - 1e-3..=80. + 1e-3).contains(&r), "{p:?} is {r} out");
+        }
+    }
+
+    #[test]
+    fn poisson_disc_points_keep_their_spacing() {
+        let mut spawner = Spawner::new(window(), Region::PoissonDisc { spacing: 12. });
+        let points = spawner.points.clone();
+        assert!(points.len() > 100);
+        for (i, &p) in points.iter().enumerate() {
+            assert!(window().contains(p), "{p:?}");
+            for &q in &points[i + 1..] {
+                assert!(p.distance(q) >= 12., "{p:?} and {q:?} are too close");
+            }
+        }
+        for p in positions(&mut spawner) {
+            assert!(points.contains(&p));
+        }
+    }
+
+    #[test]
+    fn mask_points_land_on_bright_pixels() {
+        // Only the pixel in column 2 of row 1 is lit
+        let image = image::GrayImage::from_fn(4, 4, |x, y| {
+            image::Luma([if (x, y) == (2, 1) { 255 } else { 0 }])
+        });
+        let mut spawner = Spawner {
+            region: Region::Mask("test"),
+            window: window(),
+            mask: running_total(&image),
+            mask_size: image.dimensions(),
+            points: vec![],
+            next: 0,
+        };
+        let w = window();
+        for p in positions(&mut spawner) {
+            assert!((w.left() + 200. ..=w.left() + 300.).contains(&p.x), "{p:?}");
+            assert!((w.top() - 150. ..=w.top() - 75.).contains(&p.y), "{p:?}");
+        }
+    }
+}
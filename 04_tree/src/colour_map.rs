@@ -0,0 +1,184 @@
+use nannou::color::LinSrgb;
+use nannou::prelude::*;
+
+const LUT_SIZE: usize = 256;
+
+// What a particle's colour follows
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    // Pixels moved since the last frame
+    Speed,
+    // Direction of travel in turns, 0 to 1 anticlockwise from the right
+    Heading,
+    // Frames since it spawned
+    Age,
+    // Pixels from the middle of the window
+    Distance,
+    // The noise field under it, -1 to 1
+    Noise,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColourMap {
+    pub mapping: Mapping,
+    // Positions from 0 to 1 and `#rrggbb` colours, in order
+    pub stops: &'static [(f32, &'static str)],
+    // Values of `mapping` at either end of the gradient, clamped past them
+    pub range: (f32, f32),
+}
+
+// `ColourMap` with its gradient blended ahead of time. Stops are blended in
+// OKLab, so the colours in between keep an even brightness instead of
+// dipping dark or grey the way they do blended as RGB.
+pub struct Lut {
+    map: ColourMap,
+    colours: Vec<[f32; 3]>,
+}
+
+impl Lut {
+    pub fn new(map: ColourMap) -> Lut {
+        if map.stops.is_empty() {
+            panic!("colour map needs at least one stop");
+        }
+        let stops: Vec<(f32, [f32; 3])> = map
+            .stops
+            .iter()
+            .map(|&(position, hex)| (position, to_oklab(parse_hex(hex))))
+            .collect();
+
+        let colours = (0..LUT_SIZE)
+            .map(|i| {
+                let t = i as f32 / (LUT_SIZE - 1) as f32;
+                let after = stops.iter().position(|&(position, _)| position > t);
+                let lab = match after {
+                    Some(0) => stops[0].1,
+                    None => stops[stops.len() - 1].1,
+                    Some(i) => {
+                        let (left, from) = stops[i - 1];
+                        let (right, to) = stops[i];
+                        let f = (t - left) / (right - left);
+                        [0, 1, 2].map(|c| from[c] + (to[c] - from[c]) * f)
+                    }
+                };
+                from_oklab(lab)
+            })
+            .collect();
+
+        Lut { map, colours }
+    }
+
+    // `noise` is only called for `Mapping::Noise`
+    pub fn colour<F: FnOnce() -> f64>(
+        &self,
+        last_position: Vec2,
+        position: Vec2,
+        age: u64,
+        noise: F,
+    ) -> LinSrgb {
+        let travel = position - last_position;
+        let value = match self.map.mapping {
+            Mapping::Speed => travel.length(),
+            Mapping::Heading => travel.y.atan2(travel.x).rem_euclid(2. * PI) / (2. * PI),
+            Mapping::Age => age as f32,
+            Mapping::Distance => position.length(),
+            Mapping::Noise => noise() as f32,
+        };
+
+        let (low, high) = self.map.range;
+        let t = ((value - low) / (high - low)).clamp(0., 1.);
+        let [r, g, b] = self.colours[(t * (LUT_SIZE - 1) as f32).round() as usize];
+
+        LinSrgb::new(r, g, b)
+    }
+}
+
+// `#rrggbb` to linear RGB
+fn parse_hex(hex: &str) -> [f32; 3] {
+    let digits = hex.trim_start_matches('#');
+    if digits.len() != 6 {
+        panic!("bad colour `{}` in colour map", hex);
+    }
+
+    [0, 2, 4].map(|i| {
+        let channel = u8::from_str_radix(&digits[i..i + 2], 16)
+            .unwrap_or_else(|_| panic!("bad colour `{}` in colour map", hex));
+        let c = channel as f32 / 255.;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+// Björn Ottosson's matrices, from linear RGB
+fn to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+fn from_oklab([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    let l = (lightness + 0.396_337_78 * a + 0.215_803_76 * b).powi(3);
+    let m = (lightness - 0.105_561_346 * a - 0.063_854_17 * b).powi(3);
+    let s = (lightness - 0.089_484_18 * a - 1.291_485_5 * b).powi(3);
+
+    [
+        4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+        -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+        -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+    ]
+    .map(|c| c.clamp(0., 1.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(colour: LinSrgb, hex: &str) {
+        let expected = parse_hex(hex);
+        let actual = [colour.red, colour.green, colour.blue];
+        for c in 0..3 {
+            assert!(
+                (actual[c] - expected[c]).abs() < 1e-3,
+                "{actual:?} isn't {hex} {expected:?}"
+            );
+        }
+    }
+
+    fn lut(stops: &'static [(f32, &'static str)]) -> Lut {
+        Lut::new(ColourMap {
+            mapping: Mapping::Age,
+            stops,
+            range: (0., 100.),
+        })
+    }
+
+    fn colour_at(lut: &Lut, age: u64) -> LinSrgb {
+        lut.colour(Vec2::ZERO, Vec2::ZERO, age, || 0.)
+    }
+
+    #[test]
+    fn lut_ends_are_the_end_stops() {
+        let lut = lut(&[(0., "#1b0c41"), (0.5, "#e55c30"), (1., "#fcffa4")]);
+        assert_close(colour_at(&lut, 0), "#1b0c41");
+        assert_close(colour_at(&lut, 100), "#fcffa4");
+        assert_close(colour_at(&lut, 500), "#fcffa4");
+    }
+
+    #[test]
+    fn stops_short_of_the_ends_hold_their_colour() {
+        let lut = lut(&[(0.2, "#ff0000"), (0.8, "#0000ff")]);
+        assert_close(colour_at(&lut, 0), "#ff0000");
+        assert_close(colour_at(&lut, 20), "#ff0000");
+        assert_close(colour_at(&lut, 80), "#0000ff");
+        assert_close(colour_at(&lut, 100), "#0000ff");
+    }
+}
@@ -1,7 +1,59 @@
+use crate::accumulation::ToneMap;
+use crate::boundary::Boundary;
+use crate::colour_map::ColourMap;
+use crate::noise_field::{Basis, Layering, NoiseSettings};
+use crate::overlay::FieldView;
+use crate::spawn::Region;
+
 pub const DEBUG_LOGGING: bool = true;
 pub const CAPTURE_OUTPUT: bool = true;
 pub const PARTICLE_COUNT: i32 = 10000;
+// Seeds every particle's random numbers, the same seed gives the same run
+pub const SEED: u64 = 0;
+// None uses every core
+pub const THREAD_COUNT: Option<usize> = None;
 pub const CAPTURE_FRAMES: u64 = 2000; // 2000 frames ~= 33 seconds
 pub const HEIGHT: u32 = 960;
 pub const WIDTH: u32 = 540;
 pub const RADIUS: f32 = 70.;
+pub const NOISE: NoiseSettings = NoiseSettings {
+    basis: Basis::Perlin,
+    layering: Layering::Single,
+    octaves: 4,
+    lacunarity: 2.,
+    persistence: 0.5,
+    seed: 0,
+    scale: (128., 137.),
+    speed: 0.01,
+};
+pub const FIELD_VIEW: FieldView = FieldView::Off;
+pub const HEAT_MAP: bool = false;
+// Stops time on this frame, as if space was pressed
+pub const FREEZE_AT: Option<u64> = None;
+// Pixels between arrows, streamlines start twice as far apart
+pub const OVERLAY_SPACING: f32 = 24.;
+pub const HEAT_MAP_CELL: f32 = 8.;
+pub const BOUNDARY: Boundary = Boundary::Respawn;
+// Pixels past the edge of the window a particle can go before `BOUNDARY`
+// kicks in, negative keeps them further inside
+pub const BOUNDARY_MARGIN: f32 = 10.;
+// `None` spawns in the clumps of leaves on the tree
+pub const SPAWN: Option<Region> = None;
+// Where particles that left the window start over, the circle of `RADIUS`
+pub const RESPAWN: Region = Region::Annulus {
+    x: 0.,
+    y: 0.,
+    inner: RADIUS,
+    outer: RADIUS,
+};
+// Colours strokes by speed, heading, age, distance or noise, `None` keeps
+// them flat black
+pub const COLOUR_MAP: Option<ColourMap> = None;
+// Fade trails in a floating point buffer instead of under a translucent rect
+pub const ACCUMULATE: bool = false;
+// Share of the buffer left after each frame
+pub const DECAY: f32 = 0.9;
+pub const TONE_MAP: ToneMap = ToneMap::Reinhard;
+pub const EXPOSURE: f32 = 1.;
+// Only used by `ToneMap::Gamma`
+pub const TONE_GAMMA: f32 = 2.2;
@@ -1,12 +1,29 @@
 use config::PARTICLE_COUNT;
 #[allow(unused_imports)]
 use log::{warn, LevelFilter};
-use nannou::noise::NoiseFn;
+use nannou::color::{IntoLinSrgba, LinSrgba};
 use nannou::prelude::*;
+use nannou::rand::rand::rngs::SmallRng;
+use nannou::rand::rand::Rng;
+use std::time::Instant;
 
+mod accumulation;
+mod boundary;
 mod capture;
+mod colour_map;
 mod config;
 mod logger;
+mod noise_field;
+mod overlay;
+mod parallel;
+mod spawn;
+
+use accumulation::{Accumulator, Blend};
+use boundary::Boundary;
+use colour_map::Lut;
+use noise_field::NoiseField;
+use overlay::Overlay;
+use spawn::Spawner;
 
 static LOGGER: logger::SimpleLogger = logger::SimpleLogger {
     enabled: config::DEBUG_LOGGING,
@@ -15,14 +32,17 @@ static LOGGER: logger::SimpleLogger = logger::SimpleLogger {
 fn main() {
     let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Warn));
 
-    nannou::app(model)
-        .size(config::WIDTH, config::HEIGHT)
-        .update(update)
-        .simple_window(view)
-        .run();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bench") {
+        benchmark();
+        return;
+    }
+
+    nannou::app(model).update(update).run();
 }
 
 struct Particle {
+    born: u64,
     original_pos: Vec2,
     sin_offset: f32,
     pos: Vec2,
@@ -31,21 +51,24 @@ struct Particle {
     exit_frame: u64,
     collision_frame: u64,
     collision_end: u64,
+    rng: SmallRng,
 }
 
 impl Particle {
-    fn new(x: f32, y: f32) -> Particle {
-        let exit_frame = (random_f32() * 80000.) as u64;
-        let collision_frame = exit_frame + (random_f32() * 600.) as u64 + 500;
+    fn new(x: f32, y: f32, base_frame: u64, mut rng: SmallRng) -> Particle {
+        let exit_frame = (rng.gen::<f32>() * 80000.) as u64 + base_frame;
+        let collision_frame = exit_frame + (rng.gen::<f32>() * 600.) as u64 + 500;
         Particle {
+            born: base_frame,
             original_pos: vec2(x, y),
-            sin_offset: random_f32(),
+            sin_offset: rng.gen::<f32>(),
             pos: vec2(x, y),
             last_pos: vec2(x, y),
             vel: vec2(0., 0.),
             exit_frame,
             collision_frame,
-            collision_end: collision_frame + (random_f32() + 100.) as u64,
+            collision_end: collision_frame + (rng.gen::<f32>() + 100.) as u64,
+            rng,
         }
     }
 
@@ -65,28 +88,74 @@ impl Particle {
         }
     }
 
-    fn reset(&mut self, base_frame: u64) {
-        let theta = random_f32() * 2. * PI;
-
-        let x = config::RADIUS * theta.cos();
-        let y = config::RADIUS * theta.sin();
-
-        self.pos = vec2(x, y);
-        self.last_pos = vec2(x, y);
+    // Hangs from `position` until it falls again, wherever it first spawned
+    fn reset(&mut self, position: Vec2, base_frame: u64) {
+        self.born = base_frame;
+        self.original_pos = position;
+        self.pos = position;
+        self.last_pos = position;
         self.vel = vec2(0., 0.);
-        self.exit_frame = (random_f32() * 30000.) as u64 + base_frame;
-        self.collision_frame = self.exit_frame + (random_f32() * 1000.) as u64 + 300;
-        self.collision_end = self.collision_frame + (random_f32() + 100.) as u64
+        self.exit_frame = (self.rng.gen::<f32>() * 30000.) as u64 + base_frame;
+        self.collision_frame = self.exit_frame + (self.rng.gen::<f32>() * 1000.) as u64 + 300;
+        self.collision_end = self.collision_frame + (self.rng.gen::<f32>() + 100.) as u64
     }
 }
 
 struct Model {
     particles: Vec<Particle>,
+    noise: NoiseField,
+    overlay: Overlay,
+    // `None` spawns in the clumps of leaves
+    spawner: Option<Spawner>,
+    respawner: Spawner,
+    lut: Option<Lut>,
+    accumulator: Option<Accumulator>,
+    frame: u64,
     tree: wgpu::Texture,
     tree_inverted: wgpu::Texture,
 }
 
 fn model(app: &App) -> Model {
+    app.new_window()
+        .size(config::WIDTH, config::HEIGHT)
+        .view(view)
+        .key_pressed(key_pressed)
+        .build()
+        .unwrap();
+
+    // Set up tree images
+    let assets = app.assets_path().unwrap();
+    let tree_path = assets.join("tree.png");
+    let tree_inverted_path = assets.join("tree_inverted.png");
+    let tree = wgpu::Texture::from_path(app, tree_path).unwrap();
+    let tree_inverted = wgpu::Texture::from_path(app, tree_inverted_path).unwrap();
+
+    let window = app.window_rect();
+    let mut spawner = config::SPAWN.map(|region| Spawner::new(window, region));
+    let particles = (0..PARTICLE_COUNT as usize)
+        .map(|i| spawn(spawner.as_mut(), parallel::stream(i), 0))
+        .collect();
+
+    Model {
+        particles,
+        noise: NoiseField::new(&config::NOISE),
+        overlay: Overlay::new(),
+        spawner,
+        respawner: Spawner::new(window, config::RESPAWN),
+        lut: config::COLOUR_MAP.map(Lut::new),
+        accumulator: config::ACCUMULATE.then(|| Accumulator::new(app, Blend::Ink)),
+        frame: 0,
+        tree,
+        tree_inverted,
+    }
+}
+
+fn spawn(spawner: Option<&mut Spawner>, mut rng: SmallRng, base_frame: u64) -> Particle {
+    if let Some(spawner) = spawner {
+        let position = spawner.position(&mut rng);
+        return Particle::new(position.x, position.y, base_frame, rng);
+    }
+
     let groups = [
         // left
         vec2(-60., 85.),
@@ -114,106 +183,305 @@ fn model(app: &App) -> Model {
         vec2(40., 70.),
     ];
 
-    // Set up tree images
-    let assets = app.assets_path().unwrap();
-    let tree_path = assets.join("tree.png");
-    let tree_inverted_path = assets.join("tree_inverted.png");
-    let tree = wgpu::Texture::from_path(app, tree_path).unwrap();
-    let tree_inverted = wgpu::Texture::from_path(app, tree_inverted_path).unwrap();
+    let group_index = (rng.gen::<f32>() * (groups.len() - 1) as f32).round();
+    let group = groups[group_index as usize];
 
-    let mut p = vec![];
-    for _i in 0..PARTICLE_COUNT {
-        let group_index = (random_f32() * (groups.len() - 1) as f32).round();
-        let group = groups[group_index as usize];
+    let r = 15.;
+    let tetha = rng.gen::<f32>() * 2. * PI;
+    let x = group.x + r * rng.gen::<f32>() * tetha.cos();
+    let y = group.y + r * rng.gen::<f32>() * tetha.sin() * 0.5;
+    Particle::new(x, y, base_frame, rng)
+}
+
+// Blown sideways and always down
+fn force(noise: &NoiseField, position: Vec2, frame: u64, i: usize) -> Vec2 {
+    let force = noise.vector(position, frame, i as f64 / 1000.);
+    vec2(force.x, -force.y.abs())
+}
 
-        let r = 15.;
-        let tetha = random_f32() * 2. * PI;
-        let x = group.x + r * random_f32() * tetha.cos();
-        let y = group.y + r * random_f32() * tetha.sin() * 0.5;
-        p.push(Particle::new(x, y));
+fn update(app: &App, model: &mut Model, _update: Update) {
+    let window = app.window_rect();
+    let frame = model.overlay.tick(app.elapsed_frames());
+    if model.overlay.frozen {
+        return;
     }
+    model.frame = frame;
 
-    Model {
-        particles: p,
-        tree,
-        tree_inverted,
+    step(
+        &mut model.particles,
+        &model.noise,
+        model.spawner.as_mut(),
+        &mut model.respawner,
+        window,
+        frame,
+        parallel::thread_count(),
+    );
+
+    if config::ACCUMULATE {
+        let mut strokes = vec![(
+            vec2(window.left(), 0.),
+            vec2(window.right(), 0.),
+            rgba(0., 0., 0., 0.01).into_lin_srgba(),
+        )];
+        for p in &model.particles {
+            if frame < p.collision_frame {
+                strokes.push((p.last_pos, p.pos, stroke(model, p)));
+            } else if frame < p.collision_end {
+                // The ellipse as a ring of short strokes, half as strong for
+                // its half width outline
+                let radius = collision_radius(p, frame);
+                let point = |i: usize| {
+                    let angle = i as f32 / 32. * 2. * PI;
+                    p.pos + vec2(angle.cos() * radius, angle.sin() * radius / 2.)
+                };
+                for i in 0..32 {
+                    let colour = rgba(0., 0., 0., 0.025).into_lin_srgba();
+                    strokes.push((point(i), point(i + 1), colour));
+                }
+            }
+        }
+
+        let accumulator = model.accumulator.as_mut().unwrap();
+        accumulator.decay();
+        for (from, to, colour) in strokes {
+            accumulator.deposit(from, to, colour);
+        }
+        accumulator.develop();
     }
 }
 
-fn is_out_of_frame(frame: Rect, pos: Vec2) -> bool {
-    (pos.x < frame.left() || pos.x > frame.right())
-        && (pos.y < frame.bottom() || pos.y > frame.top())
+// Moves every particle one frame, spread over `threads` cores
+fn step(
+    particles: &mut [Particle],
+    noise: &NoiseField,
+    mut spawner: Option<&mut Spawner>,
+    respawner: &mut Spawner,
+    window: Rect,
+    frame: u64,
+    threads: usize,
+) {
+    // Seconds at 60 frames a second, the same every run unlike `app.time`
+    let time = frame as f32 / 60.;
+    let outside: Vec<usize> = (0..particles.len())
+        .filter(|&i| boundary::is_outside(window, particles[i].pos))
+        .collect();
+
+    parallel::for_each(particles, threads, |i, p| {
+        if !boundary::is_outside(window, p.pos) {
+            let force = force(noise, p.pos, frame, i);
+            p.update(time, frame, force.x, force.y);
+        }
+    });
+
+    // In order, one thread, the spawners are shared
+    for i in outside {
+        let p = &mut particles[i];
+        match config::BOUNDARY {
+            Boundary::Wrap => boundary::wrap(window, &mut p.pos, &mut p.last_pos),
+            Boundary::Bounce => boundary::bounce(window, &mut p.pos, &mut p.vel),
+            Boundary::Respawn => {
+                let position = respawner.position(&mut p.rng);
+                p.reset(position, frame);
+            }
+            Boundary::Kill => *p = spawn(spawner.as_deref_mut(), p.rng.clone(), frame),
+        }
+    }
 }
 
-fn update(app: &App, model: &mut Model, _update: Update) {
-    let window = app.window_rect();
-    let frame = app.elapsed_frames();
-    let t = frame as f64 / 100.;
-    let noise = nannou::noise::Perlin::new();
-
-    for i in 0..model.particles.len() {
-        let p = &mut model.particles[i];
-
-        // let x = noise.get([p.pos.x, p.pos.y, t + i]);
-        let x = noise.get([
-            p.pos.x as f64 / 128.,
-            p.pos.y as f64 / 137.,
-            t + i as f64 / 1000.,
-        ]) as f32;
-        let y = noise.get([
-            -p.pos.y as f64 / 128.,
-            p.pos.x as f64 / 137.,
-            t + i as f64 / 1000.,
-        ]) as f32;
-
-        if is_out_of_frame(window, p.pos) {
-            p.reset(frame);
-        } else {
-            p.update(app.time, frame, x, -y.abs());
+// `cargo run --release -- bench`, times a few seconds of steps at each
+// particle count without opening a window
+fn benchmark() {
+    const COUNTS: [usize; 3] = [10_000, 100_000, 1_000_000];
+    const SECONDS: f32 = 3.;
+
+    let window = Rect::from_w_h(config::WIDTH as f32, config::HEIGHT as f32);
+    let noise = NoiseField::new(&config::NOISE);
+    let threads = parallel::thread_count();
+    warn!("Benchmark on {} threads", threads);
+
+    for count in COUNTS {
+        let mut spawner = config::SPAWN.map(|region| Spawner::new(window, region));
+        let mut respawner = Spawner::new(window, config::RESPAWN);
+        let mut particles: Vec<Particle> = (0..count)
+            .map(|i| spawn(spawner.as_mut(), parallel::stream(i), 0))
+            .collect();
+
+        let start = Instant::now();
+        let mut frame = 0;
+        while frame < 3 || start.elapsed().as_secs_f32() < SECONDS {
+            step(
+                &mut particles,
+                &noise,
+                spawner.as_mut(),
+                &mut respawner,
+                window,
+                frame,
+                threads,
+            );
+            frame += 1;
         }
+        let steps = frame as f32 / start.elapsed().as_secs_f32();
+        warn!("{:>9} particles: {:.1} steps/s", count, steps);
     }
 }
 
+// Collisions grow from nothing to 10 pixels
+fn collision_radius(p: &Particle, frame: u64) -> f32 {
+    10. - (p.collision_end - frame) as f32 / (p.collision_end - p.collision_frame) as f32 * 10.
+}
+
+fn key_pressed(_app: &App, model: &mut Model, key: Key) {
+    model.overlay.key_pressed(key);
+}
+
 fn view(app: &App, model: &Model, frame: Frame) {
     let draw = app.draw();
     let window = app.window_rect();
-    let frame_count = frame.nth();
+    // Particles count frames without the time spent frozen
+    let frame_count = model.frame;
 
-    if frame_count == 0 {
-        frame.clear(WHITE)
-    }
+    if let Some(accumulator) = &model.accumulator {
+        accumulator.draw(app, &draw, &frame);
+        draw.texture(&model.tree).y(45.).w_h(100., 100.);
+        draw.texture(&model.tree_inverted).y(-20.).w_h(100., 40.);
+    } else {
+        // Trails would smear the overlay
+        if frame.nth() == 0 || model.overlay.is_visible() {
+            frame.clear(WHITE)
+        }
+
+        draw.texture(&model.tree).y(45.).w_h(100., 100.);
+        draw.texture(&model.tree_inverted).y(-20.).w_h(100., 40.);
+
+        draw.line()
+            .color(rgba(0., 0., 0., 0.01))
+            .start(vec2(window.left(), 0.))
+            .end(vec2(window.right(), 0.));
 
-    draw.texture(&model.tree).y(45.).w_h(100., 100.);
-    draw.texture(&model.tree_inverted).y(-20.).w_h(100., 40.);
-
-    draw.line()
-        .color(rgba(0., 0., 0., 0.01))
-        .start(vec2(window.left(), 0.))
-        .end(vec2(window.right(), 0.));
-
-    draw.rect()
-        .w_h(config::WIDTH as f32, config::HEIGHT as f32)
-        .x_y(0., 0.)
-        .color(rgba(1., 1., 1., 0.1));
-
-    for p in &model.particles {
-        if frame_count < p.collision_frame {
-            draw.line().start(p.last_pos).end(p.pos).color(BLACK);
-        } else if frame_count < p.collision_end {
-            let radius = 10.
-                - (p.collision_end - frame_count) as f32
-                    / (p.collision_end - p.collision_frame) as f32
-                    * 10.;
-            draw.ellipse()
-                .x_y(p.pos.x, p.pos.y)
-                .w_h(radius * 2., radius)
-                .color(rgba(0., 0., 0., 0.))
-                .stroke_weight(0.5)
-                .stroke(rgba(0., 0., 0., 0.05));
+        draw.rect()
+            .w_h(config::WIDTH as f32, config::HEIGHT as f32)
+            .x_y(0., 0.)
+            .color(rgba(1., 1., 1., 0.1));
+
+        for p in &model.particles {
+            if frame_count < p.collision_frame {
+                draw.line()
+                    .start(p.last_pos)
+                    .end(p.pos)
+                    .color(stroke(model, p));
+            } else if frame_count < p.collision_end {
+                let radius = collision_radius(p, frame_count);
+                draw.ellipse()
+                    .x_y(p.pos.x, p.pos.y)
+                    .w_h(radius * 2., radius)
+                    .color(rgba(0., 0., 0., 0.))
+                    .stroke_weight(0.5)
+                    .stroke(rgba(0., 0., 0., 0.05));
+            }
         }
     }
 
+    model
+        .overlay
+        .draw(&draw, window, &model.noise, |position, frame| {
+            // Particle 0's time slice, the rest are offset from it
+            force(&model.noise, position, frame, 0)
+        });
+
     draw.to_frame(app, &frame).unwrap();
 
     capture::capture(app, frame)
 }
+
+fn stroke(model: &Model, p: &Particle) -> LinSrgba {
+    match &model.lut {
+        Some(lut) => lut
+            .colour(
+                p.last_pos,
+                p.pos,
+                model.frame.saturating_sub(p.born),
+                || model.noise.get(p.pos, model.frame, 0.),
+            )
+            .into_lin_srgba(),
+        None => BLACK.into_lin_srgba(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Furthest the hovering wobble takes a leaf from where it hangs
+    const WOBBLE: f32 = 6.;
+
+    #[test]
+    fn reset_particles_hang_from_their_new_position() {
+        let mut p = Particle::new(-30., 100., 0, parallel::stream(0));
+        let anchor = vec2(200., -300.);
+        p.reset(anchor, 10);
+        p.update(11. / 60., 11, 0., 0.);
+
+        assert!(p.pos.distance(anchor) <= WOBBLE, "{:?}", p.pos);
+        assert_eq!(p.last_pos, anchor);
+    }
+
+    #[test]
+    fn leaves_leaving_the_window_respawn_on_the_circle() {
+        // Only the `Respawn` policy sends leaves to `RESPAWN`
+        if config::BOUNDARY != Boundary::Respawn {
+            return;
+        }
+
+        let window = Rect::from_w_h(config::WIDTH as f32, config::HEIGHT as f32);
+        let noise = NoiseField::new(&config::NOISE);
+        let mut respawner = Spawner::new(window, config::RESPAWN);
+        let mut particles = vec![Particle::new(-30., 100., 0, parallel::stream(0))];
+        particles[0].pos = vec2(window.right() + 50., 0.);
+
+        step(&mut particles, &noise, None, &mut respawner, window, 1, 1);
+        let anchor = particles[0].pos;
+        assert!(
+            (anchor.length() - config::RADIUS).abs() < 1e-3,
+            "{:?}",
+            anchor
+        );
+
+        step(&mut particles, &noise, None, &mut respawner, window, 2, 1);
+        assert!(
+            particles[0].pos.distance(anchor) <= WOBBLE,
+            "{:?}",
+            particles[0].pos
+        );
+    }
+    #[test]
+    fn steps_come_out_the_same_on_any_number_of_threads() {
+        let window = Rect::from_w_h(config::WIDTH as f32, config::HEIGHT as f32);
+        let noise = NoiseField::new(&config::NOISE);
+        let run = |threads| {
+            let mut spawner = config::SPAWN.map(|region| Spawner::new(window, region));
+            let mut respawner = Spawner::new(window, config::RESPAWN);
+            // Enough particles that every thread gets some
+            let mut particles: Vec<Particle> = (0..4 * parallel::MIN_CHUNK)
+                .map(|i| spawn(spawner.as_mut(), parallel::stream(i), 0))
+                .collect();
+            // Falling straight away rather than after hovering for minutes
+            for p in &mut particles {
+                p.exit_frame = 0;
+            }
+            for frame in 0..30 {
+                step(
+                    &mut particles,
+                    &noise,
+                    spawner.as_mut(),
+                    &mut respawner,
+                    window,
+                    frame,
+                    threads,
+                );
+            }
+            particles.iter().map(|p| p.pos).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(1), run(4));
+    }
+}
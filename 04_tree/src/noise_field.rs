@@ -0,0 +1,195 @@
+use nannou::noise::{Fbm, MultiFractal, NoiseFn, OpenSimplex, Seedable, Value, Worley};
+use nannou::prelude::*;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Basis {
+    Perlin,
+    OpenSimplex,
+    // Cellular, flat patches with sharp edges between them
+    Worley,
+    // Smoothed random values on a grid, blockier than Perlin
+    Value,
+}
+
+// How octaves of the basis are layered
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layering {
+    // The basis alone, `octaves` is ignored
+    Single,
+    // Octaves added up, each finer and fainter than the last
+    Fbm,
+    // Octaves folded into sharp crests where they cross zero
+    Ridged,
+    // Octaves folded into sharp valleys where they cross zero
+    Turbulence,
+}
+
+pub struct NoiseSettings {
+    pub basis: Basis,
+    pub layering: Layering,
+    pub octaves: usize,
+    // Frequency multiplier from one octave to the next
+    pub lacunarity: f64,
+    // Amplitude multiplier from one octave to the next
+    pub persistence: f64,
+    pub seed: u32,
+    // Pixels per unit of noise along x and y
+    pub scale: (f64, f64),
+    // Units of noise along the time axis per frame
+    pub speed: f64,
+}
+
+type Generator = Box<dyn NoiseFn<[f64; 3]> + Send + Sync>;
+
+// A 3D noise function sampled at a position in the window and a frame,
+// values run from -1 to 1. Build it once, generators set up permutation tables.
+pub struct NoiseField {
+    octaves: Vec<Generator>,
+    layering: Layering,
+    lacunarity: f64,
+    persistence: f64,
+    scale: (f64, f64),
+    speed: f64,
+}
+
+impl NoiseField {
+    pub fn new(settings: &NoiseSettings) -> NoiseField {
+        let count = match settings.layering {
+            Layering::Single => 1,
+            _ => settings.octaves.max(1),
+        };
+        let octaves = (0..count)
+            .map(|i| generator(settings.basis, settings.seed.wrapping_add(i as u32)))
+            .collect();
+
+        NoiseField {
+            octaves,
+            layering: settings.layering,
+            lacunarity: settings.lacunarity,
+            persistence: settings.persistence,
+            scale: settings.scale,
+            speed: settings.speed,
+        }
+    }
+
+    // `offset` moves along the time axis, in noise units, so things sampling
+    // the same spot don't all move in step
+    pub fn get(&self, position: Vec2, frame: u64, offset: f64) -> f64 {
+        self.sample(self.point(position, frame, offset))
+    }
+
+    // Two lookups a quarter turn apart, a direction to push particles in
+    pub fn vector(&self, position: Vec2, frame: u64, offset: f64) -> Vec2 {
+        vec2(
+            self.get(position, frame, offset) as f32,
+            self.get(vec2(-position.y, position.x), frame, offset) as f32,
+        )
+    }
+
+    fn point(&self, position: Vec2, frame: u64, offset: f64) -> [f64; 3] {
+        [
+            position.x as f64 / self.scale.0,
+            position.y as f64 / self.scale.1,
+            frame as f64 * self.speed + offset,
+        ]
+    }
+
+    fn sample(&self, point: [f64; 3]) -> f64 {
+        if self.layering == Layering::Single {
+            return self.octaves[0].get(point);
+        }
+
+        let mut total = 0.;
+        let mut weight = 0.;
+        let mut amplitude = 1.;
+        let mut frequency = 1.;
+        for octave in &self.octaves {
+            let value = octave.get(point.map(|v| v * frequency));
+            total += amplitude
+                * match self.layering {
+                    Layering::Ridged => 1. - 2. * value.abs(),
+                    Layering::Turbulence => 2. * value.abs() - 1.,
+                    _ => value,
+                };
+            weight += amplitude;
+            amplitude *= self.persistence;
+            frequency *= self.lacunarity;
+        }
+
+        total / weight
+    }
+}
+
+fn generator(basis: Basis, seed: u32) -> Generator {
+    match basis {
+        // noise 0.7 exports two `Perlin`s so the name is ambiguous, a single
+        // octave of its fBm is exactly the classic one
+        Basis::Perlin => Box::new(Fbm::new().set_octaves(1).set_seed(seed)),
+        Basis::OpenSimplex => Box::new(OpenSimplex::new().set_seed(seed)),
+        Basis::Worley => Box::new(Worley::new().set_seed(seed)),
+        Basis::Value => Box::new(Value::new().set_seed(seed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(basis: Basis, layering: Layering) -> NoiseSettings {
+        NoiseSettings {
+            basis,
+            layering,
+            octaves: 4,
+            lacunarity: 2.,
+            persistence: 0.5,
+            seed: 7,
+            scale: (100., 80.),
+            speed: 0.01,
+        }
+    }
+
+    fn samples(field: &NoiseField) -> Vec<f64> {
+        (0..400)
+            .map(|i| {
+                let position = vec2((i % 20) as f32 * 37. - 370., (i / 20) as f32 * 29. - 290.);
+                field.get(position, i as u64, 0.25)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn layered_values_stay_in_range() {
+        for basis in [
+            Basis::Perlin,
+            Basis::OpenSimplex,
+            Basis::Worley,
+            Basis::Value,
+        ] {
+            for layering in [Layering::Fbm, Layering::Ridged, Layering::Turbulence] {
+                let field = NoiseField::new(&settings(basis, layering));
+                for value in samples(&field) {
+                    assert!(
+                        (-1. ..=1.).contains(&value),
+                        "{basis:?} {layering:?} gave {value}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_values() {
+        for layering in [
+            Layering::Single,
+            Layering::Fbm,
+            Layering::Ridged,
+            Layering::Turbulence,
+        ] {
+            let first = NoiseField::new(&settings(Basis::Perlin, layering));
+            let second = NoiseField::new(&settings(Basis::Perlin, layering));
+            assert_eq!(samples(&first), samples(&second), "{layering:?}");
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use log::warn;
+use nannou::prelude::*;
+
+use crate::config;
+use crate::noise_field::NoiseField;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldView {
+    Off,
+    // An arrow on every grid point, longer and redder where the push is stronger
+    Arrows,
+    // Paths a particle without any inertia would follow, coloured the same way
+    Streamlines,
+}
+
+// Debug view of the flow under the particles. F cycles through the field
+// views, H toggles a heat map of the noise values, space freezes time and
+// the left and right arrow keys step the frozen frame.
+pub struct Overlay {
+    pub field: FieldView,
+    pub heat_map: bool,
+    pub frozen: bool,
+    frame: u64,
+    // Frames spent frozen, left out so time picks up where it stopped
+    paused: i64,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay {
+            field: config::FIELD_VIEW,
+            heat_map: config::HEAT_MAP,
+            frozen: false,
+            frame: 0,
+            paused: 0,
+        }
+    }
+
+    // Call once per update, gives the frame the sketch is at
+    pub fn tick(&mut self, elapsed: u64) -> u64 {
+        if self.frozen {
+            self.paused = elapsed as i64 - self.frame as i64;
+        } else {
+            self.frame = (elapsed as i64 - self.paused).max(0) as u64;
+            if config::FREEZE_AT == Some(self.frame) {
+                self.frozen = true;
+                warn!("Frozen at frame {}", self.frame);
+            }
+        }
+
+        self.frame
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.field != FieldView::Off || self.heat_map
+    }
+
+    pub fn key_pressed(&mut self, key: Key) {
+        match key {
+            Key::F => {
+                self.field = match self.field {
+                    FieldView::Off => FieldView::Arrows,
+                    FieldView::Arrows => FieldView::Streamlines,
+                    FieldView::Streamlines => FieldView::Off,
+                }
+            }
+            Key::H => self.heat_map = !self.heat_map,
+            Key::Space => self.frozen = !self.frozen,
+            Key::Right if self.frozen => self.frame += 1,
+            Key::Left if self.frozen => self.frame = self.frame.saturating_sub(1),
+            _ => return,
+        }
+
+        warn!(
+            "Overlay {:?}, heat map {}, frame {}{}",
+            self.field,
+            self.heat_map,
+            self.frame,
+            if self.frozen { " (frozen)" } else { "" }
+        );
+    }
+
+    // `force` is the push a particle at a position gets on a frame. Where
+    // each particle reads the noise a little further along in time, it's the
+    // push on particle 0, the others are pushed by time slices not shown.
+    pub fn draw<F>(&self, draw: &Draw, window: Rect, noise: &NoiseField, force: F)
+    where
+        F: Fn(Vec2, u64) -> Vec2,
+    {
+        if self.heat_map {
+            heat_map(draw, window, noise, self.frame);
+        }
+
+        match self.field {
+            FieldView::Off => {}
+            FieldView::Arrows => arrows(draw, window, self.frame, &force),
+            FieldView::Streamlines => streamlines(draw, window, self.frame, &force),
+        }
+    }
+}
+
+fn heat_map(draw: &Draw, window: Rect, noise: &NoiseField, frame: u64) {
+    let size = config::HEAT_MAP_CELL;
+    for point in grid(window, size) {
+        let value = noise.get(point, frame, 0.) as f32;
+        draw.rect()
+            .xy(point)
+            .w_h(size, size)
+            .color(heat((value + 1.) / 2., 0.5));
+    }
+}
+
+fn arrows<F: Fn(Vec2, u64) -> Vec2>(draw: &Draw, window: Rect, frame: u64, force: &F) {
+    let spacing = config::OVERLAY_SPACING;
+    let points = grid(window, spacing);
+    let forces: Vec<Vec2> = points.iter().map(|&point| force(point, frame)).collect();
+    let strongest = strongest(&forces);
+
+    for (&point, &force) in points.iter().zip(&forces) {
+        let strength = force.length() / strongest;
+        if strength < 0.05 {
+            continue;
+        }
+
+        // The strongest arrow spans most of a grid cell
+        draw.arrow()
+            .start(point)
+            .end(point + force / strongest * spacing * 0.9)
+            .weight(1.)
+            .head_length(4.)
+            .head_width(2.)
+            .color(heat(strength, 0.9));
+    }
+}
+
+fn streamlines<F: Fn(Vec2, u64) -> Vec2>(draw: &Draw, window: Rect, frame: u64, force: &F) {
+    const STEPS: usize = 40;
+    const STEP: f32 = 3.;
+
+    let seeds = grid(window, config::OVERLAY_SPACING * 2.);
+    let forces: Vec<Vec2> = seeds.iter().map(|&seed| force(seed, frame)).collect();
+    let strongest = strongest(&forces);
+
+    for seed in seeds {
+        let mut point = seed;
+        let mut line = Vec::with_capacity(STEPS);
+        for _ in 0..STEPS {
+            let here = force(point, frame);
+            if here.length() < 1e-6 || !window.contains(point) {
+                break;
+            }
+            line.push((point, heat(here.length() / strongest, 0.8)));
+
+            // Midpoint step, plain Euler steps spiral outwards around vortices
+            let middle = force(point + here.normalize() * STEP / 2., frame);
+            if middle.length() < 1e-6 {
+                break;
+            }
+            point += middle.normalize() * STEP;
+        }
+
+        if line.len() > 1 {
+            draw.polyline().weight(1.).points_colored(line);
+        }
+    }
+}
+
+// Centres of the cells of a grid covering the window
+fn grid(window: Rect, spacing: f32) -> Vec<Vec2> {
+    let columns = (window.w() / spacing).ceil() as usize;
+    let rows = (window.h() / spacing).ceil() as usize;
+
+    (0..rows)
+        .flat_map(|row| {
+            (0..columns).map(move |column| {
+                vec2(
+                    window.left() + (column as f32 + 0.5) * spacing,
+                    window.bottom() + (row as f32 + 0.5) * spacing,
+                )
+            })
+        })
+        .collect()
+}
+
+fn strongest(forces: &[Vec2]) -> f32 {
+    forces
+        .iter()
+        .map(|force| force.length())
+        .fold(0., f32::max)
+        .max(1e-6)
+}
+
+// Blue for 0 through to red for 1
+fn heat(t: f32, alpha: f32) -> Hsla {
+    hsla(0.66 * (1. - t.clamp(0., 1.)), 1., 0.5, alpha)
+}
@@ -0,0 +1,56 @@
+use std::path::{Path, PathBuf};
+
+use nannou::App;
+use serde::{Deserialize, Serialize};
+
+use crate::precision::Fixed;
+use crate::viewport::Viewport;
+
+// A saved position. The centre is kept as decimal text so it survives past
+// f64 precision, rename entries freely in the file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub re: String,
+    pub im: String,
+    pub scale: f64,
+}
+
+impl Bookmark {
+    pub fn from_viewport(name: String, viewport: &Viewport) -> Bookmark {
+        Bookmark {
+            name,
+            re: viewport.re.to_decimal(),
+            im: viewport.im.to_decimal(),
+            scale: viewport.scale,
+        }
+    }
+
+    pub fn centre(&self, bits: u32) -> Option<(Fixed, Fixed)> {
+        Some((Fixed::parse(&self.re, bits)?, Fixed::parse(&self.im, bits)?))
+    }
+}
+
+pub fn bookmarks_path(app: &App) -> PathBuf {
+    app.project_path()
+        .expect("failed to locate `project_path`")
+        .join("bookmarks.json")
+}
+
+// A missing file just means nothing has been saved yet
+pub fn load(path: &Path) -> Vec<Bookmark> {
+    if !path.exists() {
+        return vec![];
+    }
+
+    nannou::io::load_from_json(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+pub fn save(path: &Path, bookmarks: &[Bookmark]) {
+    nannou::io::save_to_json(path, &bookmarks)
+        .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+}
+
+pub fn find<'a>(bookmarks: &'a [Bookmark], name: &str) -> Option<&'a Bookmark> {
+    bookmarks.iter().find(|b| b.name == name)
+}
@@ -0,0 +1,411 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use log::warn;
+use nannou::image::{Rgba, RgbaImage};
+use nannou::rand::rand::rngs::SmallRng;
+use nannou::rand::rand::{Rng, SeedableRng};
+use nannou::App;
+
+use crate::config;
+use crate::kernel;
+use crate::pool::Pool;
+use crate::render;
+use crate::viewport::Viewport;
+
+const MAGIC: &[u8; 8] = b"BUDDHA01";
+// Every c outside this square escapes on the first step and draws nothing
+const RADIUS: f64 = 2.;
+// Cells per side of the importance grid over that square
+const GRID: usize = 128;
+// Points tried per cell to guess how much it adds to the view
+const PROBES: usize = 4;
+// Share of the samples spread evenly, so cells the guess missed still get some
+const UNIFORM_SHARE: f64 = 0.1;
+const BATCH_SAMPLES: u64 = 10_000;
+// Periodicity tolerance, a cycling orbit is thrown away so a miss only costs time
+const EPSILON: f64 = 1e-12;
+const SEED: u64 = 0x6275_6464_6861;
+
+// Maps points of an orbit onto pixels of the view, the inverse of
+// `Viewport::pixel_offset` in f64
+struct Projection {
+    re: f64,
+    im: f64,
+    scale: f64,
+    sin: f64,
+    cos: f64,
+    width: usize,
+    height: usize,
+}
+
+impl Projection {
+    fn new(viewport: &Viewport) -> Projection {
+        let (sin, cos) = viewport.rotation.sin_cos();
+        Projection {
+            re: viewport.re.to_f64(),
+            im: viewport.im.to_f64(),
+            scale: viewport.scale,
+            sin,
+            cos,
+            width: config::WIDTH as usize,
+            height: config::HEIGHT as usize,
+        }
+    }
+
+    fn pixel(&self, re: f64, im: f64) -> Option<usize> {
+        let x = (im - self.im) / self.scale;
+        let y = (re - self.re) / self.scale;
+        let px = x * self.cos + y * self.sin + self.width as f64 / 2.;
+        let py = y * self.cos - x * self.sin + self.height as f64 / 2.;
+        if px < 0. || py < 0. || px >= self.width as f64 || py >= self.height as f64 {
+            return None;
+        }
+
+        Some(py as usize * self.width + px as usize)
+    }
+}
+
+// Where in the square to pick c. Cells whose orbits cross the view more often
+// get picked more, and every sample is weighted by how much likelier its cell
+// was than under even sampling, so the image converges to the same density.
+struct Importance {
+    cdf: Vec<f64>,
+    probability: Vec<f64>,
+}
+
+impl Importance {
+    fn uniform() -> Importance {
+        Importance::from_weights(&[0.; GRID * GRID])
+    }
+
+    fn from_weights(weights: &[f64]) -> Importance {
+        let total: f64 = weights.iter().sum();
+        let even = 1. / weights.len() as f64;
+        let probability: Vec<f64> = weights
+            .iter()
+            .map(|w| {
+                if total > 0. {
+                    UNIFORM_SHARE * even + (1. - UNIFORM_SHARE) * w / total
+                } else {
+                    even
+                }
+            })
+            .collect();
+
+        let mut running = 0.;
+        let cdf = probability
+            .iter()
+            .map(|p| {
+                running += p;
+                running
+            })
+            .collect();
+
+        Importance { cdf, probability }
+    }
+
+    // c and the weight of its orbit
+    fn sample(&self, rng: &mut SmallRng) -> (f64, f64, f64) {
+        let u = rng.gen::<f64>() * self.cdf[self.cdf.len() - 1];
+        let cell = self.cdf.partition_point(|&c| c < u).min(self.cdf.len() - 1);
+        let (re, im) = cell_point(cell, rng);
+        let weight = 1. / (self.probability.len() as f64 * self.probability[cell]);
+
+        (re, im, weight)
+    }
+}
+
+fn cell_point(cell: usize, rng: &mut SmallRng) -> (f64, f64) {
+    let size = 2. * RADIUS / GRID as f64;
+    let re = -RADIUS + ((cell % GRID) as f64 + rng.gen::<f64>()) * size;
+    let im = -RADIUS + ((cell / GRID) as f64 + rng.gen::<f64>()) * size;
+
+    (re, im)
+}
+
+// Follows the orbit of c, pushing the pixels it lands on, and returns the
+// step it escaped at. Points that stay in the set push nothing.
+fn trace(
+    re: f64,
+    im: f64,
+    max_iteration: i32,
+    projection: &Projection,
+    pixels: &mut Vec<u32>,
+) -> Option<i32> {
+    let (sample, _) = kernel::escape_time(re, im, max_iteration, EPSILON, None, None);
+    if sample.iteration >= max_iteration {
+        return None;
+    }
+
+    // Second time through now it's known to escape, there's no point storing
+    // the orbits of the points that don't
+    let mut x = 0.;
+    let mut y = 0.;
+    for _ in 0..sample.iteration {
+        let xtemp = x * x - y * y + re;
+        y = 2. * x * y + im;
+        x = xtemp;
+        if let Some(pixel) = projection.pixel(x, y) {
+            pixels.push(pixel as u32);
+        }
+    }
+
+    Some(sample.iteration)
+}
+
+// Orbits of points that escape, plotted where they go rather than where they
+// start. The three iteration limits go into red, green and blue (Nebulabrot);
+// an orbit counts towards every channel whose limit it escaped within.
+pub struct Buddhabrot {
+    pool: Pool,
+    viewport: Viewport,
+    projection: Arc<Projection>,
+    importance: Arc<Importance>,
+    histogram: Vec<[f64; 3]>,
+    // Each batch seeds its own generator, so a resumed render carries on
+    // exactly as if it had never stopped
+    batches: u64,
+    path: PathBuf,
+}
+
+impl Buddhabrot {
+    // Picks up the state in `path` when it was rendered with the same view and limits
+    pub fn new(viewport: &Viewport, path: PathBuf) -> Buddhabrot {
+        let mut buddhabrot = Buddhabrot {
+            pool: Pool::new(render::thread_count()),
+            viewport: viewport.clone(),
+            projection: Arc::new(Projection::new(viewport)),
+            importance: Arc::new(Importance::uniform()),
+            histogram: vec![[0.; 3]; (config::WIDTH * config::HEIGHT) as usize],
+            batches: 0,
+            path,
+        };
+        buddhabrot.reset(viewport);
+
+        if buddhabrot.path.exists() {
+            match buddhabrot.load() {
+                Ok(()) => warn!(
+                    "Resumed {} at {} samples",
+                    buddhabrot.path.display(),
+                    buddhabrot.samples()
+                ),
+                Err(e) => warn!("Starting over, {}: {}", buddhabrot.path.display(), e),
+            }
+        }
+
+        buddhabrot
+    }
+
+    // Starts over on another view, keeping the threads and the state file
+    pub fn reset(&mut self, viewport: &Viewport) {
+        self.viewport = viewport.clone();
+        self.projection = Arc::new(Projection::new(viewport));
+        self.histogram.fill([0.; 3]);
+        self.batches = 0;
+        self.importance = Arc::new(if config::BUDDHABROT_IMPORTANCE {
+            self.probe()
+        } else {
+            Importance::uniform()
+        });
+    }
+
+    pub fn viewport(&self) -> &Viewport {
+        &self.viewport
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.batches * BATCH_SAMPLES
+    }
+
+    // Counts how many orbit points from a few probes in each cell land in the view
+    fn probe(&self) -> Importance {
+        let projection = self.projection.clone();
+        let max_iteration = max_limit();
+        let rows: Vec<usize> = (0..GRID).collect();
+        let (weights, _) = self.pool.run(&rows, move |row| {
+            let mut rng = SmallRng::seed_from_u64(SEED ^ row as u64);
+            let mut pixels = vec![];
+            (row * GRID..(row + 1) * GRID)
+                .map(|cell| {
+                    pixels.clear();
+                    for _ in 0..PROBES {
+                        let (re, im) = cell_point(cell, &mut rng);
+                        trace(re, im, max_iteration, &projection, &mut pixels);
+                    }
+                    pixels.len() as f64
+                })
+                .collect::<Vec<f64>>()
+        });
+
+        Importance::from_weights(&weights.concat())
+    }
+
+    // One frame's worth of samples, spread over the pool a batch per job
+    pub fn sample(&mut self) {
+        let batch_count = (config::BUDDHABROT_SAMPLES / BATCH_SAMPLES).max(1) as usize;
+        let first = self.batches;
+        let projection = self.projection.clone();
+        let importance = self.importance.clone();
+        let limits = config::BUDDHABROT_LIMITS;
+        let max_iteration = max_limit();
+
+        let order: Vec<usize> = (0..batch_count).collect();
+        let (batches, _) = self.pool.run(&order, move |i| {
+            let mut rng = SmallRng::seed_from_u64(SEED.wrapping_add(first + i as u64));
+            let mut pixels = vec![];
+            let mut orbits = vec![];
+            for _ in 0..BATCH_SAMPLES {
+                let (re, im, weight) = importance.sample(&mut rng);
+                let start = pixels.len();
+                if let Some(iteration) = trace(re, im, max_iteration, &projection, &mut pixels) {
+                    let channels = limits.map(|limit| iteration < limit);
+                    orbits.push((start..pixels.len(), channels, weight));
+                }
+            }
+            (pixels, orbits)
+        });
+
+        for (pixels, orbits) in batches {
+            for (range, channels, weight) in orbits {
+                for &pixel in &pixels[range] {
+                    let counts = &mut self.histogram[pixel as usize];
+                    for (count, &hit) in counts.iter_mut().zip(&channels) {
+                        if hit {
+                            *count += weight;
+                        }
+                    }
+                }
+            }
+        }
+        self.batches += batch_count as u64;
+    }
+
+    // Each channel stretched so `BUDDHABROT_BLACK` of its lit pixels are
+    // black and `BUDDHABROT_WHITE` are below full brightness, then
+    // brightened by `BUDDHABROT_GAMMA`. The black point hides the haze of
+    // short orbits, which land everywhere.
+    pub fn image(&self) -> RgbaImage {
+        let width = self.projection.width;
+        let height = self.projection.height;
+        let levels: Vec<(f64, f64)> = (0..3).map(|c| self.levels(c)).collect();
+
+        let mut image = RgbaImage::new(width as u32, height as u32);
+        for (i, counts) in self.histogram.iter().enumerate() {
+            let mut pixel = [0, 0, 0, 255];
+            for c in 0..3 {
+                let (black, white) = levels[c];
+                let value = ((counts[c] - black) / (white - black)).clamp(0., 1.) as f32;
+                pixel[c] = (value.powf(1. / config::BUDDHABROT_GAMMA) * 255.).round() as u8;
+            }
+            let row = (height - 1 - i / width) as u32;
+            image.put_pixel((i % width) as u32, row, Rgba(pixel));
+        }
+
+        image
+    }
+
+    // Black and white points of a channel
+    fn levels(&self, channel: usize) -> (f64, f64) {
+        let mut counts: Vec<f64> = self
+            .histogram
+            .iter()
+            .map(|c| c[channel])
+            .filter(|&c| c > 0.)
+            .collect();
+        if counts.is_empty() {
+            return (0., 1.);
+        }
+
+        let last = (counts.len() - 1) as f64;
+        let mut level = |share: f32| {
+            let index = (last * share as f64) as usize;
+            *counts
+                .select_nth_unstable_by(index, |a, b| a.total_cmp(b))
+                .1
+        };
+        let black = level(config::BUDDHABROT_BLACK);
+        let white = level(config::BUDDHABROT_WHITE);
+
+        (black, white.max(black + f64::EPSILON))
+    }
+
+    pub fn save(&self) {
+        let write = || -> std::io::Result<()> {
+            let mut file = BufWriter::new(File::create(&self.path)?);
+            file.write_all(&self.header())?;
+            file.write_all(&self.batches.to_le_bytes())?;
+            for counts in &self.histogram {
+                for count in counts {
+                    file.write_all(&count.to_le_bytes())?;
+                }
+            }
+            file.flush()
+        };
+
+        write().unwrap_or_else(|e| panic!("{}: {}", self.path.display(), e));
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        let mut file = BufReader::new(File::open(&self.path).map_err(|e| e.to_string())?);
+        let mut read = |length: usize| -> Result<Vec<u8>, String> {
+            let mut bytes = vec![0; length];
+            file.read_exact(&mut bytes).map_err(|e| e.to_string())?;
+            Ok(bytes)
+        };
+
+        let header = self.header();
+        if read(header.len())? != header {
+            return Err("rendered with a different view or limits".to_string());
+        }
+
+        let batches = u64::from_le_bytes(read(8)?.try_into().unwrap());
+        let bytes = read(self.histogram.len() * 3 * 8)?;
+        let mut values = bytes
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()));
+        for counts in &mut self.histogram {
+            for count in counts {
+                *count = values.next().unwrap();
+            }
+        }
+        self.batches = batches;
+
+        Ok(())
+    }
+
+    // Everything the histogram depends on, a state file with another header
+    // belongs to a different render
+    fn header(&self) -> Vec<u8> {
+        let projection = &self.projection;
+        let mut header = MAGIC.to_vec();
+        header.extend((projection.width as u32).to_le_bytes());
+        header.extend((projection.height as u32).to_le_bytes());
+        for limit in config::BUDDHABROT_LIMITS {
+            header.extend(limit.to_le_bytes());
+        }
+        for value in [
+            projection.re,
+            projection.im,
+            projection.scale,
+            self.viewport.rotation,
+        ] {
+            header.extend(value.to_le_bytes());
+        }
+        header.push(config::BUDDHABROT_IMPORTANCE as u8);
+
+        header
+    }
+}
+
+fn max_limit() -> i32 {
+    config::BUDDHABROT_LIMITS.into_iter().max().unwrap()
+}
+
+pub fn state_path(app: &App) -> PathBuf {
+    app.project_path()
+        .expect("failed to locate `project_path`")
+        .join(config::BUDDHABROT_STATE)
+}
@@ -0,0 +1,200 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::precision::Fixed;
+use crate::viewport::{self, Viewport, START_SCALE};
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    #[default]
+    EaseInOut,
+    EaseOut,
+    // Stays on this keyframe until the next one
+    Hold,
+}
+
+impl Easing {
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1. - (1. - t).powi(3),
+            Easing::EaseInOut => t * t * (3. - 2. * t),
+            Easing::Hold => 0.,
+        }
+    }
+}
+
+// One stop on the path. `zoom` is magnification over the starting view and
+// `easing` shapes the move from this keyframe to the next.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Keyframe {
+    pub frame: u64,
+    pub re: String,
+    pub im: String,
+    pub zoom: f64,
+    #[serde(default)]
+    pub rotation: f64,
+    pub max_iteration: i32,
+    #[serde(default)]
+    pub palette_offset: f32,
+    #[serde(default)]
+    pub easing: Easing,
+    // Added to `TRAP_ANGLE`, degrees
+    #[serde(default)]
+    pub trap_angle: f64,
+    // Multiplies `TRAP_SIZE`
+    #[serde(default = "one")]
+    pub trap_scale: f64,
+}
+
+fn one() -> f64 {
+    1.
+}
+
+#[derive(Deserialize)]
+struct CameraFile {
+    keyframes: Vec<Keyframe>,
+}
+
+// Keyframe with its centre parsed
+struct Stop {
+    keyframe: Keyframe,
+    re: Fixed,
+    im: Fixed,
+    scale: f64,
+}
+
+// Where the camera is on a given frame
+pub struct Shot {
+    pub viewport: Viewport,
+    pub max_iteration: i32,
+    pub palette_offset: f32,
+    pub trap_angle: f64,
+    pub trap_scale: f64,
+}
+
+pub struct CameraPath {
+    stops: Vec<Stop>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<CameraPath, String> {
+        let file: CameraFile =
+            nannou::io::load_from_json(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut keyframes = file.keyframes;
+        if keyframes.is_empty() {
+            return Err(format!("{}: no keyframes", path.display()));
+        }
+        keyframes.sort_by_key(|k| k.frame);
+
+        let mut stops = vec![];
+        for keyframe in keyframes {
+            let scale = START_SCALE / keyframe.zoom;
+            let bits = viewport::precision_for(scale);
+            let re = Fixed::parse(&keyframe.re, bits);
+            let im = Fixed::parse(&keyframe.im, bits);
+            match (re, im) {
+                (Some(re), Some(im)) => stops.push(Stop {
+                    keyframe,
+                    re,
+                    im,
+                    scale,
+                }),
+                _ => return Err(format!("bad centre in keyframe {}", keyframe.frame)),
+            }
+        }
+
+        Ok(CameraPath { stops })
+    }
+
+    pub fn last_frame(&self) -> u64 {
+        self.stops.last().unwrap().keyframe.frame
+    }
+
+    pub fn at(&self, frame: u64) -> Shot {
+        let next = self
+            .stops
+            .iter()
+            .position(|s| s.keyframe.frame > frame)
+            .unwrap_or(self.stops.len());
+        if next == 0 {
+            return self.stops[0].shot();
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].shot();
+        }
+
+        let from = &self.stops[next - 1];
+        let to = &self.stops[next];
+        let length = (to.keyframe.frame - from.keyframe.frame) as f64;
+        let t = from
+            .keyframe
+            .easing
+            .apply((frame - from.keyframe.frame) as f64 / length);
+
+        // Equal steps in log zoom look like a constant zoom speed
+        let scale = (from.scale.ln() + (to.scale.ln() - from.scale.ln()) * t).exp();
+
+        // The centre moves in step with the width of the view rather than with
+        // time, which keeps the point being zoomed into still on screen instead
+        // of racing off it at the deep end
+        let progress = if (to.scale / from.scale).ln().abs() < 1e-9 {
+            t
+        } else {
+            (from.scale - scale) / (from.scale - to.scale)
+        };
+
+        let mut viewport = Viewport::new(0., 0., scale);
+        let bits = viewport.precision_bits();
+        viewport.re =
+            &from.re.with_bits(bits) + &(&to.re - &from.re).with_bits(bits).mul_f64(progress);
+        viewport.im =
+            &from.im.with_bits(bits) + &(&to.im - &from.im).with_bits(bits).mul_f64(progress);
+        viewport.rotation = lerp(from.keyframe.rotation, to.keyframe.rotation, t).to_radians();
+
+        Shot {
+            viewport,
+            max_iteration: lerp(
+                from.keyframe.max_iteration as f64,
+                to.keyframe.max_iteration as f64,
+                t,
+            )
+            .round() as i32,
+            palette_offset: lerp(
+                from.keyframe.palette_offset as f64,
+                to.keyframe.palette_offset as f64,
+                t,
+            ) as f32,
+            trap_angle: lerp(from.keyframe.trap_angle, to.keyframe.trap_angle, t),
+            trap_scale: lerp(from.keyframe.trap_scale, to.keyframe.trap_scale, t),
+        }
+    }
+}
+
+impl Stop {
+    fn shot(&self) -> Shot {
+        let mut viewport = Viewport::new(0., 0., self.scale);
+        viewport.re = self.re.clone();
+        viewport.im = self.im.clone();
+        viewport.rotation = self.keyframe.rotation.to_radians();
+
+        Shot {
+            viewport,
+            max_iteration: self.keyframe.max_iteration,
+            palette_offset: self.keyframe.palette_offset,
+            trap_angle: self.keyframe.trap_angle,
+            trap_scale: self.keyframe.trap_scale,
+        }
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
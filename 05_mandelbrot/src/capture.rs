@@ -1,18 +1,23 @@
-use nannou::{App, Frame};
+use nannou::image::RgbaImage;
+use nannou::App;
 
 use crate::config;
 
-fn captured_frame_path(app: &App, frame: &Frame) -> std::path::PathBuf {
+fn captured_frame_path(app: &App, nth: u64) -> std::path::PathBuf {
     app.project_path()
         .expect("failed to locate `project_path`")
         .join("frames")
-        .join(format!("{:04}", frame.nth()))
+        .join(format!("{:04}", nth))
         .with_extension("png")
 }
 
-pub fn capture(app: &App, frame: Frame) {
-    if frame.nth() < config::CAPTURE_FRAMES && config::CAPTURE_OUTPUT {
-        let file_path = captured_frame_path(app, &frame);
-        app.main_window().capture_frame(file_path);
+// Saves the rendered image itself rather than reading the window back
+pub fn capture(app: &App, nth: u64, image: &RgbaImage) {
+    if nth < config::CAPTURE_FRAMES && config::CAPTURE_OUTPUT {
+        let file_path = captured_frame_path(app, nth);
+        if let Some(dir) = file_path.parent() {
+            std::fs::create_dir_all(dir).expect("failed to create `frames` directory");
+        }
+        image.save(file_path).expect("failed to save frame");
     }
 }
@@ -0,0 +1,247 @@
+use nannou::color::{hsl, Srgb};
+use nannou::image::{Rgba, RgbaImage};
+use nannou::math::map_range;
+
+use crate::config;
+use crate::gradient::Gradient;
+use crate::render::{IterationBuffer, Sample};
+use crate::trap::TrapShape;
+
+const INSIDE: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const GOLDEN_RATIO: f32 = 0.618_034;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colouring {
+    // The original purple HSL ramp over raw iteration counts
+    Classic,
+    // Normalised iteration count through the palette, no banding
+    Smooth,
+    // Spreads the palette evenly over however many pixels land in each band
+    Histogram,
+    // Distance to the set in pixels, estimated from how fast the smooth count
+    // changes between neighbouring pixels
+    Distance,
+    // Distance worked out from the derivative along the orbit, exact enough
+    // to draw filaments a pixel wide at any depth
+    DistanceEstimate,
+    // Palette shaded by a light shining on the set as if it were a surface,
+    // using the normal the derivative gives
+    Lit,
+    // How close the orbit came to `TRAP`, image traps show the image
+    OrbitTrap,
+}
+
+impl Colouring {
+    pub fn from_name(name: &str) -> Option<Colouring> {
+        match name {
+            "classic" => Some(Colouring::Classic),
+            "smooth" => Some(Colouring::Smooth),
+            "histogram" => Some(Colouring::Histogram),
+            "distance" => Some(Colouring::Distance),
+            "distance_estimate" => Some(Colouring::DistanceEstimate),
+            "lit" => Some(Colouring::Lit),
+            "orbit_trap" => Some(Colouring::OrbitTrap),
+            _ => None,
+        }
+    }
+
+    pub fn needs_derivative(self) -> bool {
+        matches!(self, Colouring::DistanceEstimate | Colouring::Lit)
+    }
+}
+
+pub struct Colourer {
+    pub colouring: Colouring,
+    pub gradient: Gradient,
+    // Shifts the palette, animate it to cycle colours
+    pub offset: f32,
+}
+
+impl Colourer {
+    // Turns escape times into pixels. Image rows run top down, the buffer bottom up.
+    pub fn colour(&self, buffer: &IterationBuffer) -> RgbaImage {
+        let mut image = RgbaImage::new(buffer.width as u32, buffer.height as u32);
+
+        let cdf = self.cdf(buffer);
+
+        for y in 0..buffer.height {
+            for x in 0..buffer.width {
+                let row = (buffer.height - 1 - y) as u32;
+                let colour = self.colour_point(buffer, &cdf, x, y, buffer.get(x, y));
+                image.put_pixel(x as u32, row, colour);
+            }
+        }
+
+        image
+    }
+
+    // Colours extra samples taken inside the given pixels of `buffer`, which
+    // stands in for their surroundings
+    pub fn colour_samples(
+        &self,
+        buffer: &IterationBuffer,
+        samples: &[((i32, i32), Sample)],
+    ) -> Vec<Rgba<u8>> {
+        let cdf = self.cdf(buffer);
+        samples
+            .iter()
+            .map(|&((x, y), sample)| self.colour_point(buffer, &cdf, x, y, sample))
+            .collect()
+    }
+
+    fn cdf(&self, buffer: &IterationBuffer) -> Vec<f32> {
+        match self.colouring {
+            Colouring::Histogram => histogram(buffer),
+            _ => vec![],
+        }
+    }
+
+    fn colour_point(
+        &self,
+        buffer: &IterationBuffer,
+        cdf: &[f32],
+        x: i32,
+        y: i32,
+        sample: Sample,
+    ) -> Rgba<u8> {
+        if sample.iteration >= buffer.max_iteration {
+            return INSIDE;
+        }
+
+        // Newton basins get a colour per root, darker the longer they took to settle
+        if let Some(root) = sample.root {
+            let colour = self.gradient.sample(root as f32 * GOLDEN_RATIO);
+            return shade(
+                colour,
+                1. - (sample.iteration as f32 / config::PALETTE_PERIOD).min(0.8),
+            );
+        }
+
+        let t = match self.colouring {
+            Colouring::Classic => return classic(sample.iteration, buffer.max_iteration),
+            Colouring::Smooth => sample.smooth / config::PALETTE_PERIOD,
+            Colouring::Histogram => {
+                let smooth = sample.smooth.max(0.);
+                let band = (smooth as usize).min(cdf.len() - 2);
+                let fraction = (smooth - band as f32).min(1.);
+                cdf[band] + (cdf[band + 1] - cdf[band]) * fraction
+            }
+            Colouring::Distance => {
+                let distance = distance_estimate(buffer, x, y);
+                (1. + distance).log2() / config::DISTANCE_OCTAVES
+            }
+            Colouring::DistanceEstimate => {
+                // Fractals without a derivative fall back on the neighbours
+                let distance = sample
+                    .distance
+                    .unwrap_or_else(|| distance_estimate(buffer, x, y));
+                let colour = self
+                    .gradient
+                    .sample((1. + distance).log2() / config::DISTANCE_OCTAVES + self.offset);
+                let edge = (distance / config::FILAMENT_WIDTH).clamp(0., 1.).powf(0.25);
+                return shade(colour, edge);
+            }
+            Colouring::Lit => {
+                let colour = self
+                    .gradient
+                    .sample(sample.smooth / config::PALETTE_PERIOD + self.offset);
+                if sample.distance.is_none() {
+                    return colour;
+                }
+                return shade(colour, light(sample.normal));
+            }
+            Colouring::OrbitTrap => match (sample.trap, sample.trap_colour) {
+                (_, Some(image)) => {
+                    let under = self
+                        .gradient
+                        .sample(sample.smooth / config::PALETTE_PERIOD + self.offset);
+                    return over(Rgba(image), under);
+                }
+                (Some(distance), None) if config::TRAP != TrapShape::Image => {
+                    (distance / config::TRAP_SIZE as f32).sqrt()
+                }
+                _ => sample.smooth / config::PALETTE_PERIOD,
+            },
+        };
+
+        self.gradient.sample(t + self.offset)
+    }
+}
+
+fn classic(v: i32, max_iteration: i32) -> Rgba<u8> {
+    let gray_percentage = v as f32 / max_iteration as f32;
+    let tetha = map_range(gray_percentage, 0., 1., 0.721, 0.9);
+    let lightness = map_range(gray_percentage, 0., 1., 0., 0.5);
+    let rgb: Srgb<u8> = Srgb::from(hsl(tetha, 1.0, lightness)).into_format();
+
+    Rgba([rgb.red, rgb.green, rgb.blue, 255])
+}
+
+// Share of escaped pixels below each iteration band, 0 to 1
+fn histogram(buffer: &IterationBuffer) -> Vec<f32> {
+    let mut counts = vec![0u64; buffer.max_iteration as usize + 2];
+    let last = counts.len() - 1;
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            if !buffer.is_inside(x, y) {
+                let band = buffer.get(x, y).smooth.max(0.) as usize;
+                counts[band.min(last)] += 1;
+            }
+        }
+    }
+
+    let total = counts.iter().sum::<u64>().max(1) as f32;
+    let mut running = 0;
+    counts
+        .iter()
+        .map(|count| {
+            let below = running as f32 / total;
+            running += count;
+            below
+        })
+        .collect()
+}
+
+// The potential G = ln|z| / 2^n satisfies ln G = -ln 2 (smooth - 1), so
+// G / |grad G| works out to 1 / (ln 2 |grad smooth|), in pixels
+fn distance_estimate(buffer: &IterationBuffer, x: i32, y: i32) -> f32 {
+    let smooth = |x: i32, y: i32| {
+        let x = x.clamp(0, buffer.width - 1);
+        let y = y.clamp(0, buffer.height - 1);
+        buffer.get(x, y).smooth
+    };
+
+    let dx = (smooth(x + 1, y) - smooth(x - 1, y)) / 2.;
+    let dy = (smooth(x, y + 1) - smooth(x, y - 1)) / 2.;
+    let gradient = (dx * dx + dy * dy).sqrt().max(1e-6);
+
+    1. / (std::f32::consts::LN_2 * gradient)
+}
+
+fn shade(colour: Rgba<u8>, brightness: f32) -> Rgba<u8> {
+    let Rgba([r, g, b, a]) = colour;
+    let darken = |c: u8| (c as f32 * brightness) as u8;
+    Rgba([darken(r), darken(g), darken(b), a])
+}
+
+// Lambert shading of the surface with normal `normal`, lit from
+// `LIGHT_ANGLE` at `LIGHT_HEIGHT` above the plane
+fn light(normal: (f32, f32)) -> f32 {
+    let (sin, cos) = config::LIGHT_ANGLE.to_radians().sin_cos();
+    let height = config::LIGHT_HEIGHT;
+    let brightness = (normal.0 * cos + normal.1 * sin + height) / (1. + height);
+
+    brightness.max(0.)
+}
+
+// `top` composited over `bottom` by its alpha
+fn over(top: Rgba<u8>, bottom: Rgba<u8>) -> Rgba<u8> {
+    let alpha = top[3] as f32 / 255.;
+    let mut pixel = bottom;
+    for i in 0..3 {
+        pixel[i] = (top[i] as f32 * alpha + bottom[i] as f32 * (1. - alpha)).round() as u8;
+    }
+
+    pixel
+}
@@ -1,9 +1,124 @@
+use crate::colouring::Colouring;
+use crate::export::ExportFormat;
+use crate::fractal::FractalKind;
+use crate::reuse::Reuse;
+use crate::supersample::Supersampling;
+use crate::trap::TrapShape;
+
 pub const DEBUG_LOGGING: bool = true;
 pub const CAPTURE_OUTPUT: bool = true;
 pub const CAPTURE_FRAMES: u64 = 4000; // 2000 frames ~= 33 seconds
 pub const HEIGHT: u32 = 960;
 pub const WIDTH: u32 = 540;
-pub const TARGET_X: f32 = (0.6098177325391534147025 + 0.6098871786531954068915) / 2.;
-pub const TARGET_Y: f32 = (-0.5233795246370209316450 - 0.5232869298182982753950) / 2.;
-pub const SCALE_FACTOR: f32 = 0.995;
-pub const THREAD_COUNT: i32 = 10;
+// Decimal strings so the target keeps its digits past f64 precision
+pub const TARGET_RE: &str = "-0.5233332272276596035200";
+pub const TARGET_IM: &str = "0.6098524555961744107970";
+pub const TARGET_PRECISION_BITS: u32 = 256;
+pub const SCALE_FACTOR: f64 = 0.995;
+pub const APPROACH_RATE: f64 = 0.01;
+// Below this many units per pixel f64 can't tell pixels apart, switch to perturbation
+pub const PERTURBATION_SCALE: f64 = 1e-12;
+// None uses every core
+pub const THREAD_COUNT: Option<usize> = None;
+pub const TILE_SIZE: i32 = 32;
+// Iterate several pixels at once when the CPU supports it
+pub const SIMD: bool = true;
+// Check the SIMD kernel against the scalar one on startup and use scalar if
+// they disagree. `cargo test` runs the same check.
+pub const VERIFY_KERNEL: bool = false;
+// Orbits returning within this fraction of a pixel count as cycling
+pub const PERIODICITY_TOLERANCE: f64 = 1e-3;
+pub const SHOW_HUD: bool = true;
+pub const COLOURING: Colouring = Colouring::Smooth;
+// Relative to `assets`, `.ggr`, `.gpl` or `.json`
+pub const PALETTE: &str = "palettes/ultra.json";
+// Iterations per trip through the palette
+pub const PALETTE_PERIOD: f32 = 64.;
+// Palette offset added every frame
+pub const PALETTE_CYCLE_SPEED: f32 = 0.002;
+// Doublings of the distance estimate per trip through the palette
+pub const DISTANCE_OCTAVES: f32 = 8.;
+pub const FRACTAL: FractalKind = FractalKind::Mandelbrot;
+// Mouse and keyboard instead of flying straight at the target: scroll zooms
+// about the cursor, drag pans, click flies towards the point, space stops,
+// B saves a bookmark and 1-9 fly to the saved ones
+pub const INTERACTIVE: bool = false;
+// Name of an entry in `bookmarks.json` to fly to instead of `TARGET_RE` and
+// `TARGET_IM`, the zoom stops at its depth
+pub const TARGET_BOOKMARK: Option<&str> = None;
+// Zoom per notch of the scroll wheel
+pub const SCROLL_ZOOM: f64 = 0.8;
+pub const MAX_ITERATION: i32 = 2000;
+// Relative to `assets`, keyframes that drive the capture run in place of the
+// target, see `assets/camera/tour.json`
+pub const CAMERA_PATH: Option<&str> = None;
+// Carry escape times over from the previous frame where the pixels line up.
+// `Reuse::Exact` only lines up when panning by whole pixels, so zooms and
+// capture runs still compute every pixel. For those try `Reuse::Preview`
+// with `VERIFY_REUSE` on to see how far off it comes out.
+pub const REUSE: Reuse = Reuse::Exact;
+// Pixels a `Reuse::Preview` sample may drift in total before it's computed again
+pub const REUSE_DISTANCE: f32 = 0.25;
+// Also render every frame from scratch and log how far off reused pixels are
+pub const VERIFY_REUSE: bool = false;
+// Interactive mode first draws every `PROGRESSIVE_STEP`-th pixel each way, then
+// halves the step every frame until the view is complete
+pub const PROGRESSIVE_STEP: i32 = 8;
+// Mariani-Silver: rectangles whose border all escaped at the same count are
+// filled in without iterating inside them. Only the Mandelbrot and Multibrot
+// sets are connected enough for it, other fractals ignore it.
+pub const SUBDIVIDE: bool = true;
+// Rectangles narrower than this many pixels are computed in full
+pub const SUBDIVIDE_MIN: i32 = 4;
+// Escape radius. Raise it to 100 or so for `DistanceEstimate` and `Lit`,
+// whose estimates only hold once |z| is large; keep it below 1000.
+pub const BAILOUT: f64 = 2.;
+// Pixels from the set below which `DistanceEstimate` darkens towards black
+pub const FILAMENT_WIDTH: f32 = 1.;
+// Direction of the light for `Lit` in degrees, measured in the complex plane
+// from the real axis, so it turns with the camera
+pub const LIGHT_ANGLE: f32 = 45.;
+// How high the light stands over the plane, relative to the surface
+pub const LIGHT_HEIGHT: f32 = 1.5;
+// Trap for `Colouring::OrbitTrap`, in plane units
+pub const TRAP: TrapShape = TrapShape::Cross;
+pub const TRAP_CENTRE: (f64, f64) = (0., 0.);
+// Degrees, for lines, crosses and images
+pub const TRAP_ANGLE: f64 = 0.;
+// Circle radius, image width and the distance that spans the palette
+pub const TRAP_SIZE: f64 = 0.5;
+// Degrees the trap turns every frame
+pub const TRAP_SPIN: f64 = 0.;
+// Relative to `assets`, for `TrapShape::Image`
+pub const TRAP_IMAGE: &str = "../../04_tree/assets/tree.png";
+// Draw the Buddhabrot instead: the density of escaping orbits, built up over
+// many frames. Interactive mode starts over whenever the view moves.
+pub const BUDDHABROT: bool = false;
+// Iteration limits for red, green and blue, make them equal for a plain
+// Buddhabrot or spread them out for a Nebulabrot
+pub const BUDDHABROT_LIMITS: [i32; 3] = [5000, 500, 50];
+// Orbits started every frame
+pub const BUDDHABROT_SAMPLES: u64 = 200_000;
+// Start more orbits where a quick pass found them crossing the view
+pub const BUDDHABROT_IMPORTANCE: bool = true;
+// Shares of the lit pixels in each channel left black and below full brightness
+pub const BUDDHABROT_BLACK: f32 = 0.7;
+pub const BUDDHABROT_WHITE: f32 = 0.9999;
+pub const BUDDHABROT_GAMMA: f32 = 2.;
+// Next to `bookmarks.json`, written every `BUDDHABROT_SAVE_EVERY` frames and
+// on exit, and picked up again on the next run with the same settings
+pub const BUDDHABROT_STATE: &str = "buddhabrot.state";
+pub const BUDDHABROT_SAVE_EVERY: u64 = 60;
+// Also write the iteration count, smooth count and final |z| of every captured
+// frame into `frames`, for grading elsewhere or `cargo run --release --
+// recolour frames/0001.json palette.ggr`
+pub const EXPORT: Option<ExportFormat> = None;
+// Antialiasing, several points per pixel averaged together
+pub const SUPERSAMPLING: Supersampling = Supersampling::Adaptive;
+// Points per pixel each way
+pub const SUPERSAMPLES: u32 = 3;
+// `Supersampling::Adaptive` picks pixels that differ from a neighbour by more
+// than this in some channel, in linear light from 0 to 1
+pub const SUPERSAMPLE_CONTRAST: f32 = 0.1;
+// Only when capturing frames, interactive views stay quick
+pub const SUPERSAMPLE_CAPTURE_ONLY: bool = true;
@@ -0,0 +1,495 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use nannou::image::{ImageBuffer, Luma};
+use nannou::App;
+use serde::{Deserialize, Serialize};
+
+use crate::colouring::{Colourer, Colouring};
+use crate::config;
+use crate::gradient::Gradient;
+use crate::render::{IterationBuffer, Sample};
+use crate::viewport::Viewport;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    // 16-bit grayscale, each value scaled to fill the range
+    Png,
+    // 32-bit float grayscale
+    Tiff,
+    // NumPy float32 array, height by width
+    Npy,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Png => "png",
+            ExportFormat::Tiff => "tiff",
+            ExportFormat::Npy => "npy",
+        }
+    }
+}
+
+// One quantity of the frame in its own file. Stored values times `scale` give
+// the real ones back, `scale` is 1 for the float formats.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Channel {
+    pub name: String,
+    pub file: String,
+    pub scale: f64,
+}
+
+// Written next to the data, everything needed to read it back. Rows run top
+// down like the captured frames, inside pixels have `max_iteration` iterations.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Sidecar {
+    pub format: ExportFormat,
+    pub width: u32,
+    pub height: u32,
+    pub max_iteration: i32,
+    pub re: String,
+    pub im: String,
+    pub scale: f64,
+    pub rotation: f64,
+    pub channels: Vec<Channel>,
+}
+
+type Quantity = fn(&Sample) -> f32;
+
+const CHANNELS: [(&str, Quantity); 3] = [
+    ("iteration", |s| s.iteration as f32),
+    ("smooth", |s| s.smooth),
+    ("modulus", |s| s.modulus),
+];
+
+// Writes the escape times of captured frame `nth` next to its png, as
+// `0001.json` and a file per channel
+pub fn export(app: &App, nth: u64, buffer: &IterationBuffer, viewport: &Viewport) {
+    let format = match config::EXPORT {
+        Some(format) if nth < config::CAPTURE_FRAMES && config::CAPTURE_OUTPUT => format,
+        _ => return,
+    };
+
+    let dir = app
+        .project_path()
+        .expect("failed to locate `project_path`")
+        .join("frames");
+    std::fs::create_dir_all(&dir).expect("failed to create `frames` directory");
+
+    save(&dir, &format!("{:04}", nth), format, buffer, viewport)
+        .unwrap_or_else(|e| panic!("failed to export frame {}: {}", nth, e));
+}
+
+// `stem.json` and `stem-<channel>.<extension>` in `dir`
+pub fn save(
+    dir: &Path,
+    stem: &str,
+    format: ExportFormat,
+    buffer: &IterationBuffer,
+    viewport: &Viewport,
+) -> std::io::Result<()> {
+    let sidecar = write(dir, stem, format, buffer, viewport)?;
+    nannou::io::save_to_json(dir.join(stem).with_extension("json"), &sidecar)
+        .map_err(std::io::Error::other)
+}
+
+fn write(
+    dir: &Path,
+    stem: &str,
+    format: ExportFormat,
+    buffer: &IterationBuffer,
+    viewport: &Viewport,
+) -> std::io::Result<Sidecar> {
+    let width = buffer.width as u32;
+    let height = buffer.height as u32;
+
+    let mut channels = vec![];
+    for (name, value) in CHANNELS {
+        let mut values = Vec::with_capacity((width * height) as usize);
+        for y in (0..buffer.height).rev() {
+            for x in 0..buffer.width {
+                values.push(value(&buffer.get(x, y)));
+            }
+        }
+
+        let file = format!("{}-{}.{}", stem, name, format.extension());
+        let path = dir.join(&file);
+        let scale = match format {
+            ExportFormat::Png => write_png(&path, width, height, &values)?,
+            ExportFormat::Tiff => write_tiff(&path, width, height, &values).map(|()| 1.)?,
+            ExportFormat::Npy => write_npy(&path, width, height, &values).map(|()| 1.)?,
+        };
+        channels.push(Channel {
+            name: name.to_string(),
+            file,
+            scale,
+        });
+    }
+
+    Ok(Sidecar {
+        format,
+        width,
+        height,
+        max_iteration: buffer.max_iteration,
+        re: viewport.re.to_decimal(),
+        im: viewport.im.to_decimal(),
+        scale: viewport.scale,
+        rotation: viewport.rotation,
+        channels,
+    })
+}
+
+// Stretches the values over the 16 bits and returns the scale
+fn write_png(path: &Path, width: u32, height: u32, values: &[f32]) -> std::io::Result<f64> {
+    let largest = values.iter().fold(0f32, |a, &b| a.max(b)) as f64;
+    let scale = if largest > 0. {
+        largest / u16::MAX as f64
+    } else {
+        1.
+    };
+    let pixels = values
+        .iter()
+        .map(|&v| (v.max(0.) as f64 / scale).round() as u16)
+        .collect();
+
+    let image: ImageBuffer<Luma<u16>, Vec<u16>> =
+        ImageBuffer::from_raw(width, height, pixels).unwrap();
+    image.save(path).map_err(std::io::Error::other)?;
+
+    Ok(scale)
+}
+
+// Little endian baseline TIFF, one uncompressed strip of floats
+fn write_tiff(path: &Path, width: u32, height: u32, values: &[f32]) -> std::io::Result<()> {
+    let data_length = values.len() as u32 * 4;
+    let ifd_offset = 8 + data_length;
+
+    // Tag, type (3 short, 4 long) and value, in ascending tag order
+    let entries: [(u16, u16, u32); 11] = [
+        (256, 4, width),
+        (257, 4, height),
+        (258, 3, 32),          // BitsPerSample
+        (259, 3, 1),           // Compression: none
+        (262, 3, 1),           // PhotometricInterpretation: black is zero
+        (273, 4, 8),           // StripOffsets
+        (277, 3, 1),           // SamplesPerPixel
+        (278, 4, height),      // RowsPerStrip
+        (279, 4, data_length), // StripByteCounts
+        (284, 3, 1),           // PlanarConfiguration: chunky
+        (339, 3, 3),           // SampleFormat: IEEE float
+    ];
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"II")?;
+    file.write_all(&42u16.to_le_bytes())?;
+    file.write_all(&ifd_offset.to_le_bytes())?;
+    for value in values {
+        file.write_all(&value.to_le_bytes())?;
+    }
+
+    file.write_all(&(entries.len() as u16).to_le_bytes())?;
+    for (tag, kind, value) in entries {
+        file.write_all(&tag.to_le_bytes())?;
+        file.write_all(&kind.to_le_bytes())?;
+        file.write_all(&1u32.to_le_bytes())?;
+        // Shorts sit in the first two bytes of the value field
+        match kind {
+            3 => {
+                file.write_all(&(value as u16).to_le_bytes())?;
+                file.write_all(&[0, 0])?;
+            }
+            _ => file.write_all(&value.to_le_bytes())?,
+        }
+    }
+    file.write_all(&0u32.to_le_bytes())?;
+
+    file.flush()
+}
+
+// Version 1.0 of the format, the header padded to a multiple of 64 bytes
+fn write_npy(path: &Path, width: u32, height: u32, values: &[f32]) -> std::io::Result<()> {
+    let mut header = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}",
+        height, width
+    );
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"\x93NUMPY\x01\x00")?;
+    file.write_all(&(header.len() as u16).to_le_bytes())?;
+    file.write_all(header.as_bytes())?;
+    for value in values {
+        file.write_all(&value.to_le_bytes())?;
+    }
+
+    file.flush()
+}
+
+// Reads back a frame `export` wrote, channel values top row first
+fn read(sidecar_path: &Path) -> Result<(Sidecar, Vec<Vec<f32>>), String> {
+    let sidecar: Sidecar = nannou::io::load_from_json(sidecar_path)
+        .map_err(|e| format!("{}: {}", sidecar_path.display(), e))?;
+    let dir = sidecar_path.parent().unwrap_or_else(|| Path::new("."));
+    let length = (sidecar.width * sidecar.height) as usize;
+
+    let mut channels = vec![];
+    for (name, _) in CHANNELS {
+        let channel = sidecar
+            .channels
+            .iter()
+            .find(|c| c.name == name)
+            .ok_or_else(|| format!("{}: no `{}` channel", sidecar_path.display(), name))?;
+        let path = dir.join(&channel.file);
+        let values = match sidecar.format {
+            ExportFormat::Png => read_png(&path, channel.scale),
+            ExportFormat::Tiff => read_tiff(&path),
+            ExportFormat::Npy => read_npy(&path),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        if values.len() != length {
+            return Err(format!("{}: expected {} values", path.display(), length));
+        }
+        channels.push(values);
+    }
+
+    Ok((sidecar, channels))
+}
+
+fn read_png(path: &Path, scale: f64) -> Result<Vec<f32>, String> {
+    let image = nannou::image::open(path).map_err(|e| e.to_string())?;
+    Ok(image
+        .to_luma16()
+        .pixels()
+        .map(|p| (p[0] as f64 * scale) as f32)
+        .collect())
+}
+
+// Only the layout `write_tiff` produces
+fn read_tiff(path: &Path) -> Result<Vec<f32>, String> {
+    let bytes = read_bytes(path)?;
+    let u16_at = |i: usize| {
+        bytes
+            .get(i..i + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_at = |i: usize| {
+        bytes
+            .get(i..i + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if bytes.get(0..4) != Some(b"II\x2a\x00") {
+        return Err("not a little endian TIFF".to_string());
+    }
+
+    let ifd = u32_at(4).ok_or("truncated")? as usize;
+    let count = u16_at(ifd).ok_or("truncated")? as usize;
+    let mut offset = None;
+    let mut length = None;
+    let mut float = false;
+    for i in 0..count {
+        let entry = ifd + 2 + i * 12;
+        let tag = u16_at(entry).ok_or("truncated")?;
+        let value = match u16_at(entry + 2).ok_or("truncated")? {
+            3 => u16_at(entry + 8).ok_or("truncated")? as u32,
+            _ => u32_at(entry + 8).ok_or("truncated")?,
+        };
+        match tag {
+            259 if value != 1 => return Err("compressed TIFF".to_string()),
+            273 => offset = Some(value as usize),
+            279 => length = Some(value as usize),
+            339 => float = value == 3,
+            _ => (),
+        }
+    }
+
+    let (offset, length) = offset.zip(length).ok_or("no strip")?;
+    if !float {
+        return Err("not a float TIFF".to_string());
+    }
+    let data = bytes.get(offset..offset + length).ok_or("truncated")?;
+    Ok(floats(data))
+}
+
+// Only little endian float32 arrays in C order
+fn read_npy(path: &Path) -> Result<Vec<f32>, String> {
+    let bytes = read_bytes(path)?;
+    if bytes.get(0..6) != Some(b"\x93NUMPY") {
+        return Err("not a .npy file".to_string());
+    }
+
+    let header_length = bytes
+        .get(8..10)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or("truncated")? as usize;
+    let header = bytes.get(10..10 + header_length).ok_or("truncated")?;
+    let header = std::str::from_utf8(header).map_err(|e| e.to_string())?;
+    if !header.contains("'<f4'") || header.contains("'fortran_order': True") {
+        return Err("expected little endian float32 in C order".to_string());
+    }
+
+    Ok(floats(&bytes[10 + header_length..]))
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+fn floats(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+// `recolour <frame.json> <palette> [colouring] [output.png]`: colours an
+// exported frame again without iterating anything. Colourings that need more
+// than the escape time (distance estimates, lighting, traps, Newton roots)
+// get what the exported channels allow.
+pub fn recolour(args: &[String]) {
+    let usage = "usage: recolour <frame.json> <palette> [colouring] [output.png]";
+    let (sidecar_path, palette) = match args {
+        [sidecar, palette, ..] => (PathBuf::from(sidecar), PathBuf::from(palette)),
+        _ => panic!("{}", usage),
+    };
+    let colouring = match args.get(2) {
+        Some(name) => Colouring::from_name(name)
+            .unwrap_or_else(|| panic!("unknown colouring `{}`\n{}", name, usage)),
+        None => config::COLOURING,
+    };
+    let output = args.get(3).map(PathBuf::from).unwrap_or_else(|| {
+        let stem = sidecar_path.file_stem().unwrap().to_string_lossy();
+        sidecar_path.with_file_name(format!("{}-recoloured.png", stem))
+    });
+
+    let (sidecar, channels) = read(&sidecar_path).unwrap_or_else(|e| panic!("{}", e));
+    let width = sidecar.width as i32;
+    let height = sidecar.height as i32;
+    let mut buffer = IterationBuffer::new(width, height, sidecar.max_iteration);
+    for (i, ((&iteration, &smooth), &modulus)) in channels[0]
+        .iter()
+        .zip(&channels[1])
+        .zip(&channels[2])
+        .enumerate()
+    {
+        let x = i as i32 % width;
+        let y = height - 1 - i as i32 / width;
+        buffer.set(
+            x,
+            y,
+            Sample {
+                iteration: iteration.round() as i32,
+                smooth,
+                modulus,
+                ..Sample::default()
+            },
+        );
+    }
+
+    let colourer = Colourer {
+        colouring,
+        gradient: Gradient::load(&palette).unwrap_or_else(|e| panic!("{}", e)),
+        offset: 0.,
+    };
+    colourer
+        .colour(&buffer)
+        .save(&output)
+        .unwrap_or_else(|e| panic!("{}: {}", output.display(), e));
+    warn!(
+        "Recoloured {} into {}",
+        sidecar_path.display(),
+        output.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A small frame with every channel varying, written and read back
+    fn round_trip(format: ExportFormat) {
+        let (width, height) = (7, 5);
+        let mut buffer = IterationBuffer::new(width, height, 100);
+        for y in 0..height {
+            for x in 0..width {
+                let sample = Sample {
+                    iteration: x * height + y,
+                    smooth: x as f32 + y as f32 * 0.25,
+                    modulus: 2. + (x * y) as f32 * 0.5,
+                    ..Default::default()
+                };
+                buffer.set(x, y, sample);
+            }
+        }
+        let viewport = Viewport::new(-0.75, 0.1, 1e-3);
+
+        let dir = std::env::temp_dir().join(format!("export-{}", format.extension()));
+        std::fs::create_dir_all(&dir).unwrap();
+        save(&dir, "frame", format, &buffer, &viewport).unwrap();
+        let (sidecar, channels) = read(&dir.join("frame.json")).unwrap();
+
+        assert_eq!((sidecar.width, sidecar.height), (7, 5));
+        for ((name, value), read) in CHANNELS.iter().zip(&channels) {
+            // 16-bit PNGs round to steps of the largest value over 65535,
+            // the float formats come back exactly
+            let largest = read.iter().fold(0f32, |a, &b| a.max(b));
+            let tolerance = match format {
+                ExportFormat::Png => largest / u16::MAX as f32,
+                _ => 0.,
+            };
+            for (i, &got) in read.iter().enumerate() {
+                let (x, y) = (i as i32 % width, height - 1 - i as i32 / width);
+                let expected = value(&buffer.get(x, y));
+                assert!((got - expected).abs() <= tolerance, "{} at {}", name, i);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn png_round_trips() {
+        round_trip(ExportFormat::Png);
+    }
+
+    #[test]
+    fn tiff_round_trips() {
+        round_trip(ExportFormat::Tiff);
+    }
+
+    #[test]
+    fn npy_round_trips() {
+        round_trip(ExportFormat::Npy);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let dir = std::env::temp_dir().join("export-truncated");
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, bytes) in [
+            ("short.tiff", &b"II\x2a\x00\x08"[..]),
+            ("short.npy", &b"\x93NUMPY\x01\x00\x40"[..]),
+            ("header.npy", &b"\x93NUMPY\x01\x00\x40\x00{'descr'"[..]),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).unwrap();
+            let read = if name.ends_with("tiff") {
+                read_tiff(&path)
+            } else {
+                read_npy(&path)
+            };
+            assert!(read.is_err(), "{}", name);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
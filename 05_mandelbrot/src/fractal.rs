@@ -0,0 +1,489 @@
+use std::sync::Arc;
+
+use crate::config;
+use crate::kernel::{self, Kernel};
+use crate::render::Sample;
+use crate::trap::{Trap, TrapHit};
+
+// Anything iterated per point until it escapes (or converges). The renderer,
+// pool and colouring only ever talk to this.
+pub trait EscapeTime: Send + Sync {
+    // Sample for the point (re, im) and how many iterations shortcuts skipped.
+    // `epsilon` is the periodicity tolerance, `derivative` the pixel size to
+    // track the derivative in for a distance estimate, None skips it.
+    fn iterate(
+        &self,
+        re: f64,
+        im: f64,
+        max_iteration: i32,
+        epsilon: f64,
+        derivative: Option<f64>,
+    ) -> (Sample, i32);
+
+    // A row of points at once, override when there's a faster way
+    fn escape_times(
+        &self,
+        re: &[f64],
+        im: &[f64],
+        max_iteration: i32,
+        epsilon: f64,
+        derivative: Option<f64>,
+        out: &mut [Sample],
+    ) -> u64 {
+        let mut saved = 0;
+        for (i, sample) in out.iter_mut().enumerate() {
+            let (s, skipped) = self.iterate(re[i], im[i], max_iteration, epsilon, derivative);
+            *sample = s;
+            saved += skipped as u64;
+        }
+
+        saved
+    }
+
+    // Whether deep zooms can use the perturbation reference orbit, which is
+    // only written for z^2 + c
+    fn supports_perturbation(&self) -> bool {
+        false
+    }
+
+    // Whether the set is connected, so a border that escaped all at the same
+    // count can't hide anything inside it. Subdivision relies on it.
+    fn connected(&self) -> bool {
+        false
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub enum FractalKind {
+    Mandelbrot,
+    // c travels around `centre` once every `period` frames
+    Julia {
+        centre: (f64, f64),
+        radius: f64,
+        period: f64,
+    },
+    BurningShip,
+    Tricorn,
+    // z^power + c
+    Multibrot {
+        power: u32,
+    },
+    // Newton's method on a polynomial, coefficients (re, im) from the constant term up
+    Newton {
+        coefficients: &'static [(f64, f64)],
+    },
+}
+
+impl FractalKind {
+    // Whether the formula itself changes from frame to frame
+    pub fn is_animated(self) -> bool {
+        matches!(self, FractalKind::Julia { .. })
+    }
+}
+
+// `trap` is followed along every orbit when set, Newton fractals ignore it
+pub fn build(
+    kind: FractalKind,
+    kernel: Kernel,
+    frame: u64,
+    trap: Option<Trap>,
+) -> Arc<dyn EscapeTime> {
+    match kind {
+        FractalKind::Mandelbrot => Arc::new(Mandelbrot { kernel, trap }),
+        FractalKind::Julia {
+            centre,
+            radius,
+            period,
+        } => {
+            let angle = frame as f64 / period * std::f64::consts::TAU;
+            Arc::new(Julia {
+                c: (
+                    centre.0 + radius * angle.cos(),
+                    centre.1 + radius * angle.sin(),
+                ),
+                trap,
+            })
+        }
+        FractalKind::BurningShip => Arc::new(BurningShip { trap }),
+        FractalKind::Tricorn => Arc::new(Tricorn { trap }),
+        FractalKind::Multibrot { power } => Arc::new(Multibrot {
+            power: power.max(2),
+            trap,
+        }),
+        FractalKind::Newton { coefficients } => Arc::new(Newton::new(coefficients)),
+    }
+}
+
+pub struct Mandelbrot {
+    kernel: Kernel,
+    trap: Option<Trap>,
+}
+
+impl EscapeTime for Mandelbrot {
+    fn iterate(
+        &self,
+        re: f64,
+        im: f64,
+        max_iteration: i32,
+        epsilon: f64,
+        derivative: Option<f64>,
+    ) -> (Sample, i32) {
+        kernel::escape_time(
+            re,
+            im,
+            max_iteration,
+            epsilon,
+            derivative,
+            self.trap.as_ref(),
+        )
+    }
+
+    fn escape_times(
+        &self,
+        re: &[f64],
+        im: &[f64],
+        max_iteration: i32,
+        epsilon: f64,
+        derivative: Option<f64>,
+        out: &mut [Sample],
+    ) -> u64 {
+        // The SIMD kernel doesn't follow traps
+        if self.trap.is_some() {
+            let mut saved = 0;
+            for (i, sample) in out.iter_mut().enumerate() {
+                let (s, skipped) = self.iterate(re[i], im[i], max_iteration, epsilon, derivative);
+                *sample = s;
+                saved += skipped as u64;
+            }
+            return saved;
+        }
+
+        self.kernel
+            .escape_times(re, im, max_iteration, epsilon, derivative, out)
+    }
+
+    fn supports_perturbation(&self) -> bool {
+        true
+    }
+
+    fn connected(&self) -> bool {
+        true
+    }
+}
+
+// How the derivative of a formula moves from one step to the next, given z
+// and the derivative so far
+struct Derivative<'a> {
+    start: (f64, f64),
+    step: &'a dyn Fn((f64, f64), (f64, f64)) -> (f64, f64),
+}
+
+// Shared loop for the z -> f(z) + c family, with the same periodicity check
+// as the Mandelbrot kernel
+fn escape(
+    z: (f64, f64),
+    max_iteration: i32,
+    epsilon: f64,
+    degree: f64,
+    step: impl Fn(f64, f64) -> (f64, f64),
+    derivative: Option<Derivative>,
+    trap: Option<&Trap>,
+) -> (Sample, i32) {
+    let bailout = config::BAILOUT * config::BAILOUT;
+    let (mut xi, mut yi) = z;
+    let mut dz = derivative.as_ref().map_or((0., 0.), |d| d.start);
+    let mut hit = TrapHit::default();
+    let mut check_x = xi;
+    let mut check_y = yi;
+    let mut period = 0;
+    let mut limit = 1;
+    let mut iteration = 0;
+    while xi * xi + yi * yi <= bailout && iteration < max_iteration {
+        if let Some(derivative) = &derivative {
+            dz = (derivative.step)((xi, yi), dz);
+        }
+        (xi, yi) = step(xi, yi);
+        iteration += 1;
+
+        if let Some(trap) = trap {
+            trap.track(&mut hit, (xi, yi));
+        }
+
+        if (xi - check_x).abs() <= epsilon && (yi - check_y).abs() <= epsilon {
+            return (
+                Sample::inside(max_iteration).with_trap(&hit),
+                max_iteration - iteration,
+            );
+        }
+
+        period += 1;
+        if period == limit {
+            check_x = xi;
+            check_y = yi;
+            period = 0;
+            limit *= 2;
+        }
+    }
+
+    if iteration >= max_iteration {
+        return (Sample::inside(max_iteration).with_trap(&hit), 0);
+    }
+
+    let sample = Sample::escaped(iteration, xi * xi + yi * yi, degree).with_trap(&hit);
+    if derivative.is_some() {
+        return (sample.with_derivative((xi, yi), dz), 0);
+    }
+
+    (sample, 0)
+}
+
+pub struct Julia {
+    c: (f64, f64),
+    trap: Option<Trap>,
+}
+
+impl EscapeTime for Julia {
+    fn iterate(
+        &self,
+        re: f64,
+        im: f64,
+        max_iteration: i32,
+        epsilon: f64,
+        derivative: Option<f64>,
+    ) -> (Sample, i32) {
+        let (cr, ci) = self.c;
+        // dz' = 2 z dz, against the starting point
+        let step = |z, dz| {
+            let (x, y) = mul(z, dz);
+            (2. * x, 2. * y)
+        };
+        let derivative = derivative.map(|pixel| Derivative {
+            start: (pixel, 0.),
+            step: &step,
+        });
+        escape(
+            (re, im),
+            max_iteration,
+            epsilon,
+            2.,
+            |x, y| (x * x - y * y + cr, 2. * x * y + ci),
+            derivative,
+            self.trap.as_ref(),
+        )
+    }
+}
+
+pub struct BurningShip {
+    trap: Option<Trap>,
+}
+
+impl EscapeTime for BurningShip {
+    // Neither is holomorphic, so there's no derivative to estimate distance with
+    fn iterate(
+        &self,
+        re: f64,
+        im: f64,
+        max_iteration: i32,
+        epsilon: f64,
+        _derivative: Option<f64>,
+    ) -> (Sample, i32) {
+        escape(
+            (0., 0.),
+            max_iteration,
+            epsilon,
+            2.,
+            |x, y| (x * x - y * y + re, 2. * (x * y).abs() + im),
+            None,
+            self.trap.as_ref(),
+        )
+    }
+}
+
+pub struct Tricorn {
+    trap: Option<Trap>,
+}
+
+impl EscapeTime for Tricorn {
+    // Neither is holomorphic, so there's no derivative to estimate distance with
+    fn iterate(
+        &self,
+        re: f64,
+        im: f64,
+        max_iteration: i32,
+        epsilon: f64,
+        _derivative: Option<f64>,
+    ) -> (Sample, i32) {
+        escape(
+            (0., 0.),
+            max_iteration,
+            epsilon,
+            2.,
+            |x, y| (x * x - y * y + re, -2. * x * y + im),
+            None,
+            self.trap.as_ref(),
+        )
+    }
+}
+
+pub struct Multibrot {
+    power: u32,
+    trap: Option<Trap>,
+}
+
+impl EscapeTime for Multibrot {
+    fn iterate(
+        &self,
+        re: f64,
+        im: f64,
+        max_iteration: i32,
+        epsilon: f64,
+        derivative: Option<f64>,
+    ) -> (Sample, i32) {
+        let power = self.power;
+        // dz' = power z^(power - 1) dz + 1
+        let step = move |z, dz| {
+            let (x, y) = mul(powi(z, power - 1), dz);
+            (
+                power as f64 * x + derivative.unwrap_or(0.),
+                power as f64 * y,
+            )
+        };
+        let derivative = derivative.map(|_| Derivative {
+            start: (0., 0.),
+            step: &step,
+        });
+        escape(
+            (0., 0.),
+            max_iteration,
+            epsilon,
+            power as f64,
+            |x, y| {
+                let (px, py) = powi((x, y), power);
+                (px + re, py + im)
+            },
+            derivative,
+            self.trap.as_ref(),
+        )
+    }
+
+    // Every integer power has a connected set, like z^2 + c
+    fn connected(&self) -> bool {
+        true
+    }
+}
+
+// Converging instead of escaping: the sample counts the steps until z lands on
+// a root and remembers which one, points that never settle count as inside
+pub struct Newton {
+    coefficients: Vec<(f64, f64)>,
+    derivative: Vec<(f64, f64)>,
+    roots: Vec<(f64, f64)>,
+}
+
+const NEWTON_TOLERANCE: f64 = 1e-6;
+
+impl Newton {
+    fn new(coefficients: &[(f64, f64)]) -> Newton {
+        let coefficients = coefficients.to_vec();
+        let derivative = coefficients
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(power, (re, im))| (re * power as f64, im * power as f64))
+            .collect();
+        let roots = find_roots(&coefficients);
+
+        Newton {
+            coefficients,
+            derivative,
+            roots,
+        }
+    }
+}
+
+impl EscapeTime for Newton {
+    fn iterate(
+        &self,
+        re: f64,
+        im: f64,
+        max_iteration: i32,
+        _epsilon: f64,
+        _derivative: Option<f64>,
+    ) -> (Sample, i32) {
+        let mut z = (re, im);
+        for iteration in 0..max_iteration {
+            for (root, r) in self.roots.iter().enumerate() {
+                let d = (z.0 - r.0, z.1 - r.1);
+                if d.0 * d.0 + d.1 * d.1 < NEWTON_TOLERANCE * NEWTON_TOLERANCE {
+                    let mut sample = Sample::escaped(iteration, 4., 2.);
+                    sample.root = Some(root as u8);
+                    return (sample, 0);
+                }
+            }
+
+            let p = evaluate(&self.coefficients, z);
+            let dp = evaluate(&self.derivative, z);
+            let step = div(p, dp);
+            if !step.0.is_finite() || !step.1.is_finite() {
+                break;
+            }
+            z = (z.0 - step.0, z.1 - step.1);
+        }
+
+        (Sample::inside(max_iteration), 0)
+    }
+}
+
+fn mul(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+fn div(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let d = b.0 * b.0 + b.1 * b.1;
+    ((a.0 * b.0 + a.1 * b.1) / d, (a.1 * b.0 - a.0 * b.1) / d)
+}
+
+fn powi(z: (f64, f64), power: u32) -> (f64, f64) {
+    (1..power).fold(z, |acc, _| mul(acc, z))
+}
+
+// Horner's rule, coefficients from the constant term up
+fn evaluate(coefficients: &[(f64, f64)], z: (f64, f64)) -> (f64, f64) {
+    coefficients.iter().rev().fold((0., 0.), |acc, c| {
+        let (re, im) = mul(acc, z);
+        (re + c.0, im + c.1)
+    })
+}
+
+// Durand-Kerner, all roots at once
+fn find_roots(coefficients: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let degree = coefficients.len().saturating_sub(1);
+    if degree == 0 {
+        return vec![];
+    }
+
+    let lead = coefficients[degree];
+    let monic: Vec<(f64, f64)> = coefficients.iter().map(|c| div(*c, lead)).collect();
+
+    let mut roots: Vec<(f64, f64)> = (0..degree)
+        .map(|k| powi((0.4, 0.9), k as u32 + 1))
+        .collect();
+    for _ in 0..500 {
+        for i in 0..degree {
+            let mut denominator = (1., 0.);
+            for j in 0..degree {
+                if i != j {
+                    denominator = mul(
+                        denominator,
+                        (roots[i].0 - roots[j].0, roots[i].1 - roots[j].1),
+                    );
+                }
+            }
+            let step = div(evaluate(&monic, roots[i]), denominator);
+            roots[i] = (roots[i].0 - step.0, roots[i].1 - step.1);
+        }
+    }
+
+    roots
+}
@@ -0,0 +1,237 @@
+use std::path::Path;
+
+use nannou::image::Rgba;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Blend {
+    Linear,
+    Curved,
+    Sine,
+    SphereIncreasing,
+    SphereDecreasing,
+}
+
+// Same model as a GIMP gradient: colour blends from `from` to `to` between
+// `left` and `right`, reaching the halfway colour at `mid`
+#[derive(Clone, Debug)]
+struct Segment {
+    left: f32,
+    mid: f32,
+    right: f32,
+    from: [f32; 4],
+    to: [f32; 4],
+    blend: Blend,
+}
+
+#[derive(Clone, Debug)]
+pub struct Gradient {
+    segments: Vec<Segment>,
+}
+
+#[derive(Deserialize)]
+struct JsonStop {
+    position: f32,
+    colour: String,
+}
+
+#[derive(Deserialize)]
+struct JsonGradient {
+    stops: Vec<JsonStop>,
+}
+
+impl Gradient {
+    // Evenly spaced colours that wrap back to the first, so cycling has no seam
+    pub fn from_colours(colours: &[[f32; 4]]) -> Gradient {
+        let count = colours.len();
+        let stops: Vec<(f32, [f32; 4])> = (0..=count)
+            .map(|i| (i as f32 / count as f32, colours[i % count]))
+            .collect();
+
+        Gradient::from_stops(&stops)
+    }
+
+    pub fn from_stops(stops: &[(f32, [f32; 4])]) -> Gradient {
+        let segments = stops
+            .windows(2)
+            .map(|pair| Segment {
+                left: pair[0].0,
+                mid: (pair[0].0 + pair[1].0) / 2.,
+                right: pair[1].0,
+                from: pair[0].1,
+                to: pair[1].1,
+                blend: Blend::Linear,
+            })
+            .collect();
+
+        Gradient { segments }
+    }
+
+    // Picks the loader from the extension: GIMP `.ggr` gradients, GIMP `.gpl`
+    // palettes or a `.json` list of stops
+    pub fn load(path: &Path) -> Result<Gradient, String> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let gradient = match extension {
+            "ggr" => Gradient::parse_ggr(&read(path)?),
+            "gpl" => Gradient::parse_gpl(&read(path)?),
+            "json" => {
+                let json: JsonGradient = nannou::io::load_from_json(path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                let mut stops = vec![];
+                for stop in json.stops {
+                    stops.push((stop.position, parse_hex(&stop.colour)?));
+                }
+                stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+                Ok(Gradient::from_stops(&stops))
+            }
+            _ => Err(format!("unknown palette format `{}`", extension)),
+        }?;
+
+        if gradient.segments.is_empty() {
+            return Err(format!("{}: palette has no colours", path.display()));
+        }
+
+        Ok(gradient)
+    }
+
+    fn parse_ggr(text: &str) -> Result<Gradient, String> {
+        let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+        if lines.next().map(str::trim) != Some("GIMP Gradient") {
+            return Err("missing `GIMP Gradient` header".to_string());
+        }
+
+        let mut segments = vec![];
+        for line in lines {
+            if line.starts_with("Name:") {
+                continue;
+            }
+
+            let values: Vec<f32> = line
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("bad gradient line `{}`: {}", line, e))?;
+
+            // The segment count line
+            if values.len() == 1 {
+                continue;
+            }
+            if values.len() < 13 {
+                return Err(format!("bad gradient line `{}`", line));
+            }
+
+            // Colour model (RGB or HSV) is ignored, everything blends in RGB
+            let blend = match values[11] as i32 {
+                1 => Blend::Curved,
+                2 => Blend::Sine,
+                3 => Blend::SphereIncreasing,
+                4 => Blend::SphereDecreasing,
+                _ => Blend::Linear,
+            };
+            segments.push(Segment {
+                left: values[0],
+                mid: values[1],
+                right: values[2],
+                from: [values[3], values[4], values[5], values[6]],
+                to: [values[7], values[8], values[9], values[10]],
+                blend,
+            });
+        }
+
+        Ok(Gradient { segments })
+    }
+
+    fn parse_gpl(text: &str) -> Result<Gradient, String> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some("GIMP Palette") {
+            return Err("missing `GIMP Palette` header".to_string());
+        }
+
+        let mut colours = vec![];
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("Name:")
+                || line.starts_with("Columns:")
+            {
+                continue;
+            }
+
+            let channels: Vec<f32> = line
+                .split_whitespace()
+                .take(3)
+                .map(|v| v.parse::<f32>().map(|c| c / 255.))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("bad palette line `{}`: {}", line, e))?;
+            if channels.len() < 3 {
+                return Err(format!("bad palette line `{}`", line));
+            }
+            colours.push([channels[0], channels[1], channels[2], 1.]);
+        }
+
+        if colours.is_empty() {
+            return Ok(Gradient { segments: vec![] });
+        }
+
+        Ok(Gradient::from_colours(&colours))
+    }
+
+    // Colour at `t`, which wraps around so an offset cycles the palette
+    pub fn sample(&self, t: f32) -> Rgba<u8> {
+        let t = t.rem_euclid(1.);
+        let segment = self
+            .segments
+            .iter()
+            .find(|s| t <= s.right)
+            .unwrap_or_else(|| self.segments.last().unwrap());
+
+        let length = (segment.right - segment.left).max(f32::EPSILON);
+        let position = ((t - segment.left) / length).clamp(0., 1.);
+        let mid = ((segment.mid - segment.left) / length).clamp(0.001, 0.999);
+
+        // Bend the position so the middle colour lands on `mid`, as GIMP does
+        let factor = if position <= mid {
+            0.5 * position / mid
+        } else {
+            0.5 + 0.5 * (position - mid) / (1. - mid)
+        };
+        let factor = match segment.blend {
+            Blend::Linear => factor,
+            Blend::Curved => position.powf(0.5f32.ln() / mid.ln()),
+            Blend::Sine => ((factor - 0.5) * std::f32::consts::PI).sin() / 2. + 0.5,
+            Blend::SphereIncreasing => 1. - (1. - factor * factor).sqrt(),
+            Blend::SphereDecreasing => (1. - (factor - 1.) * (factor - 1.)).sqrt(),
+        };
+
+        let mut pixel = [0; 4];
+        for (i, channel) in pixel.iter_mut().enumerate() {
+            let value = segment.from[i] + (segment.to[i] - segment.from[i]) * factor;
+            *channel = (value.clamp(0., 1.) * 255.).round() as u8;
+        }
+
+        Rgba(pixel)
+    }
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// "#rrggbb" or "#rrggbbaa"
+fn parse_hex(hex: &str) -> Result<[f32; 4], String> {
+    let digits = hex.trim_start_matches('#');
+    let channel = |i: usize| {
+        digits
+            .get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .map(|c| c as f32 / 255.)
+            .ok_or_else(|| format!("bad colour `{}`", hex))
+    };
+
+    let alpha = if digits.len() == 8 { channel(6)? } else { 1. };
+    match digits.len() {
+        6 | 8 => Ok([channel(0)?, channel(2)?, channel(4)?, alpha]),
+        _ => Err(format!("bad colour `{}`", hex)),
+    }
+}
@@ -0,0 +1,346 @@
+// Escape time loops for plain f64 iteration, one pixel at a time or a lane
+// group at a time when the CPU has the instructions for it.
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::config;
+use crate::render::Sample;
+use crate::trap::{Trap, TrapHit};
+
+pub const LANES: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kernel {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl Kernel {
+    pub fn detect() -> Kernel {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            return Kernel::Avx2;
+        }
+
+        Kernel::Scalar
+    }
+
+    // Fills `out` with the escape times of the points (re[i], im[i]) and
+    // returns how many iterations the interior shortcuts skipped. `derivative`
+    // is the pixel size to track dz/dc in, None skips it.
+    pub fn escape_times(
+        self,
+        re: &[f64],
+        im: &[f64],
+        max_iteration: i32,
+        epsilon: f64,
+        derivative: Option<f64>,
+        out: &mut [Sample],
+    ) -> u64 {
+        let mut saved = 0;
+        let mut i = 0;
+
+        #[cfg(target_arch = "x86_64")]
+        if self == Kernel::Avx2 {
+            while i + LANES <= out.len() {
+                // Safe, `detect` only picks this kernel when the CPU supports it
+                let (group, skipped) = unsafe {
+                    match derivative {
+                        Some(pixel) => escape_time_avx2::<true>(
+                            &re[i..],
+                            &im[i..],
+                            max_iteration,
+                            epsilon,
+                            pixel,
+                        ),
+                        None => escape_time_avx2::<false>(
+                            &re[i..],
+                            &im[i..],
+                            max_iteration,
+                            epsilon,
+                            0.,
+                        ),
+                    }
+                };
+                out[i..i + LANES].copy_from_slice(&group);
+                saved += skipped.iter().map(|s| *s as u64).sum::<u64>();
+                i += LANES;
+            }
+        }
+
+        while i < out.len() {
+            let (sample, skipped) =
+                escape_time(re[i], im[i], max_iteration, epsilon, derivative, None);
+            out[i] = sample;
+            saved += skipped as u64;
+            i += 1;
+        }
+
+        saved
+    }
+}
+
+// Points in the main cardioid or the period 2 bulb never escape. `margin`
+// demands the point be that far inside, for when c itself is only approximate.
+pub fn in_main_bulbs(xp: f64, yp: f64, margin: f64) -> bool {
+    let y2 = yp * yp;
+    let xq = xp - 0.25;
+    let q = xq * xq + y2;
+    let cardioid = q * (q + xq) + margin <= 0.25 * y2;
+    let bulb = (xp + 1.) * (xp + 1.) + y2 + margin <= 0.0625;
+
+    cardioid || bulb
+}
+
+// Escape time of a point and how many iterations were skipped by finding it
+// inside the set early. Orbits that come back within `epsilon` of a saved
+// point are cycling, the saved point moves at doubling intervals (Brent).
+// `trap` is followed along the orbit, which only this scalar loop does.
+pub fn escape_time(
+    xp: f64,
+    yp: f64,
+    max_iteration: i32,
+    epsilon: f64,
+    derivative: Option<f64>,
+    trap: Option<&Trap>,
+) -> (Sample, i32) {
+    if in_main_bulbs(xp, yp, 0.) {
+        return (Sample::inside(max_iteration), max_iteration);
+    }
+
+    let bailout = config::BAILOUT * config::BAILOUT;
+    let mut iteration = 0;
+    let mut xi = 0.;
+    let mut yi = 0.;
+    let mut dx = 0.;
+    let mut dy = 0.;
+    let mut hit = TrapHit::default();
+    let mut check_x = 0.;
+    let mut check_y = 0.;
+    let mut period = 0;
+    let mut limit = 1;
+    while xi * xi + yi * yi <= bailout && iteration < max_iteration {
+        // dz' = 2 z dz + 1, in pixels
+        if let Some(pixel) = derivative {
+            let dtemp = 2. * (xi * dx - yi * dy) + pixel;
+            dy = 2. * (xi * dy + yi * dx);
+            dx = dtemp;
+        }
+
+        let xtemp = xi * xi - yi * yi + xp;
+        yi = 2. * xi * yi + yp;
+
+        xi = xtemp;
+
+        iteration += 1;
+
+        if let Some(trap) = trap {
+            trap.track(&mut hit, (xi, yi));
+        }
+
+        if (xi - check_x).abs() <= epsilon && (yi - check_y).abs() <= epsilon {
+            return (
+                Sample::inside(max_iteration).with_trap(&hit),
+                max_iteration - iteration,
+            );
+        }
+
+        period += 1;
+        if period == limit {
+            check_x = xi;
+            check_y = yi;
+            period = 0;
+            limit *= 2;
+        }
+    }
+
+    let sample = Sample::escaped(iteration, xi * xi + yi * yi, 2.).with_trap(&hit);
+    if derivative.is_some() && iteration < max_iteration {
+        return (sample.with_derivative((xi, yi), (dx, dy)), 0);
+    }
+
+    (sample, 0)
+}
+
+// Same operations in the same order as `escape_time` (and no fused
+// multiply-add) so both produce identical counts. Escaped lanes keep iterating
+// but stop counting, and the loop exits once every lane has escaped or been
+// found inside. The periodicity schedule only depends on the iteration, so
+// all lanes share it.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn escape_time_avx2<const DERIVATIVE: bool>(
+    re: &[f64],
+    im: &[f64],
+    max_iteration: i32,
+    epsilon: f64,
+    pixel: f64,
+) -> ([Sample; LANES], [i32; LANES]) {
+    let xp = _mm256_loadu_pd(re.as_ptr());
+    let yp = _mm256_loadu_pd(im.as_ptr());
+    let two = _mm256_set1_pd(2.);
+    let bailout = _mm256_set1_pd(config::BAILOUT * config::BAILOUT);
+    let step = _mm256_set1_pd(pixel);
+    let one = _mm256_set1_pd(1.);
+    let eps = _mm256_set1_pd(epsilon);
+    let sign = _mm256_set1_pd(-0.);
+
+    let mut interior = [0.; LANES];
+    for (i, lane) in interior.iter_mut().enumerate() {
+        if in_main_bulbs(re[i], im[i], 0.) {
+            *lane = f64::from_bits(u64::MAX);
+        }
+    }
+    let mut shortcut = _mm256_loadu_pd(interior.as_ptr());
+
+    let mut xi = _mm256_setzero_pd();
+    let mut yi = _mm256_setzero_pd();
+    let mut check_x = _mm256_setzero_pd();
+    let mut check_y = _mm256_setzero_pd();
+    let mut period = 0;
+    let mut limit = 1;
+    let mut count = _mm256_setzero_pd();
+    let mut norm = _mm256_setzero_pd();
+    let mut dzx = _mm256_setzero_pd();
+    let mut dzy = _mm256_setzero_pd();
+    // z and dz as each lane escaped
+    let mut escaped = [_mm256_setzero_pd(); 4];
+    let mut active = _mm256_andnot_pd(shortcut, _mm256_castsi256_pd(_mm256_set1_epi64x(-1)));
+
+    for _ in 0..max_iteration {
+        let xx = _mm256_mul_pd(xi, xi);
+        let yy = _mm256_mul_pd(yi, yi);
+        let sum = _mm256_add_pd(xx, yy);
+        let inside = _mm256_cmp_pd(sum, bailout, _CMP_LE_OQ);
+        let escaping = _mm256_andnot_pd(inside, active);
+        norm = _mm256_blendv_pd(norm, sum, escaping);
+        if DERIVATIVE {
+            for (value, now) in escaped.iter_mut().zip([xi, yi, dzx, dzy]) {
+                *value = _mm256_blendv_pd(*value, now, escaping);
+            }
+        }
+        active = _mm256_and_pd(active, inside);
+        if _mm256_movemask_pd(active) == 0 {
+            break;
+        }
+        count = _mm256_add_pd(count, _mm256_and_pd(active, one));
+
+        if DERIVATIVE {
+            let dtemp = _mm256_add_pd(
+                _mm256_mul_pd(
+                    two,
+                    _mm256_sub_pd(_mm256_mul_pd(xi, dzx), _mm256_mul_pd(yi, dzy)),
+                ),
+                step,
+            );
+            dzy = _mm256_mul_pd(
+                two,
+                _mm256_add_pd(_mm256_mul_pd(xi, dzy), _mm256_mul_pd(yi, dzx)),
+            );
+            dzx = dtemp;
+        }
+
+        let xtemp = _mm256_add_pd(_mm256_sub_pd(xx, yy), xp);
+        yi = _mm256_add_pd(_mm256_mul_pd(_mm256_mul_pd(two, xi), yi), yp);
+        xi = xtemp;
+
+        let dx = _mm256_andnot_pd(sign, _mm256_sub_pd(xi, check_x));
+        let dy = _mm256_andnot_pd(sign, _mm256_sub_pd(yi, check_y));
+        let periodic = _mm256_and_pd(
+            active,
+            _mm256_and_pd(
+                _mm256_cmp_pd(dx, eps, _CMP_LE_OQ),
+                _mm256_cmp_pd(dy, eps, _CMP_LE_OQ),
+            ),
+        );
+        shortcut = _mm256_or_pd(shortcut, periodic);
+        active = _mm256_andnot_pd(periodic, active);
+
+        period += 1;
+        if period == limit {
+            check_x = xi;
+            check_y = yi;
+            period = 0;
+            limit *= 2;
+        }
+    }
+
+    // Lanes that ran out of iterations never escaped and still need their norm
+    norm = _mm256_blendv_pd(
+        norm,
+        _mm256_add_pd(_mm256_mul_pd(xi, xi), _mm256_mul_pd(yi, yi)),
+        active,
+    );
+
+    let mut counts = [0.; LANES];
+    let mut norms = [0.; LANES];
+    let mut shortcuts = [0.; LANES];
+    _mm256_storeu_pd(counts.as_mut_ptr(), count);
+    _mm256_storeu_pd(norms.as_mut_ptr(), norm);
+    _mm256_storeu_pd(shortcuts.as_mut_ptr(), shortcut);
+
+    let mut lanes = [[0.; LANES]; 4];
+    for (lane, value) in lanes.iter_mut().zip(escaped) {
+        _mm256_storeu_pd(lane.as_mut_ptr(), value);
+    }
+    let [z_re, z_im, dz_re, dz_im] = lanes;
+
+    let mut samples = [Sample::default(); LANES];
+    let mut saved = [0; LANES];
+    for i in 0..LANES {
+        if shortcuts[i].to_bits() != 0 {
+            samples[i] = Sample::inside(max_iteration);
+            saved[i] = max_iteration - counts[i] as i32;
+        } else {
+            samples[i] = Sample::escaped(counts[i] as i32, norms[i], 2.);
+            if DERIVATIVE && (counts[i] as i32) < max_iteration {
+                samples[i] = samples[i].with_derivative((z_re[i], z_im[i]), (dz_re[i], dz_im[i]));
+            }
+        }
+    }
+
+    (samples, saved)
+}
+
+// Runs the kernel against the scalar loop over a grid covering the whole set,
+// with and without the derivative, and returns how many points disagree
+pub fn verify(kernel: Kernel) -> usize {
+    let size = 256;
+    let max_iteration = 1000;
+    let epsilon = 1e-12;
+
+    let mut mismatches = 0;
+    for row in 0..size {
+        let im = -1.25 + 2.5 * row as f64 / size as f64;
+        let re: Vec<f64> = (0..size)
+            .map(|column| -2. + 2.5 * column as f64 / size as f64)
+            .collect();
+        let im = vec![im; size];
+
+        for derivative in [None, Some(1e-3)] {
+            let mut out = vec![Sample::default(); size];
+            kernel.escape_times(&re, &im, max_iteration, epsilon, derivative, &mut out);
+
+            mismatches += (0..size)
+                .filter(|&i| {
+                    let (expected, _) =
+                        escape_time(re[i], im[i], max_iteration, epsilon, derivative, None);
+                    out[i].iteration != expected.iteration || out[i].distance != expected.distance
+                })
+                .count();
+        }
+    }
+
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detected_kernel_matches_scalar() {
+        assert_eq!(verify(Kernel::detect()), 0);
+    }
+}
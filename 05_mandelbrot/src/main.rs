@@ -1,11 +1,43 @@
 use log::warn;
-#[allow(unused_variables)]
 #[allow(unused_imports)]
 use log::{info, LevelFilter};
+use std::sync::Arc;
+
+use nannou::image::RgbaImage;
 use nannou::prelude::*;
+mod bookmarks;
+mod buddhabrot;
+mod camera;
 mod capture;
+mod colouring;
 mod config;
+mod export;
+mod fractal;
+mod gradient;
+mod kernel;
 mod logger;
+mod perturbation;
+mod pool;
+mod precision;
+mod render;
+mod reuse;
+mod subdivide;
+mod supersample;
+mod trap;
+mod viewport;
+
+use bookmarks::Bookmark;
+use buddhabrot::Buddhabrot;
+use camera::CameraPath;
+use colouring::{Colourer, Colouring};
+use gradient::Gradient;
+use precision::Fixed;
+use render::IterationBuffer;
+use trap::{Trap, TrapShape};
+use viewport::{Viewport, START_SCALE};
+
+// Less movement than this between press and release counts as a click
+const CLICK_DISTANCE: f32 = 3.;
 
 static LOGGER: logger::SimpleLogger = logger::SimpleLogger {
     enabled: config::DEBUG_LOGGING,
@@ -14,187 +46,441 @@ static LOGGER: logger::SimpleLogger = logger::SimpleLogger {
 fn main() {
     let _ = log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Warn));
 
-    nannou::app(model)
-        .size(config::WIDTH, config::HEIGHT)
-        .update(update)
-        .simple_window(view)
-        .run();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("recolour") {
+        export::recolour(&args[2..]);
+        return;
+    }
+
+    nannou::app(model).update(update).exit(exit).run();
+}
+
+struct Drag {
+    last: Point2,
+    moved: f32,
 }
 
 struct Model {
-    x: f32,
-    y: f32,
-    w: f32,
-    h: f32,
-}
-
-impl Clone for Model {
-    fn clone(&self) -> Self {
-        Model {
-            x: self.x,
-            y: self.y,
-            w: self.w,
-            h: self.h,
+    viewport: Viewport,
+    target_re: Fixed,
+    target_im: Fixed,
+    // Smallest scale to zoom to, zero keeps going
+    zoom_limit: f64,
+    flying: bool,
+    // Interactive mode only renders again after the view changes
+    dirty: bool,
+    // Pixel step of the next progressive pass, 0 once the view is complete
+    step: i32,
+    drag: Option<Drag>,
+    bookmarks: Vec<Bookmark>,
+    buffer: Option<IterationBuffer>,
+    camera: Option<CameraPath>,
+    buddhabrot: Option<Buddhabrot>,
+    trap_image: Option<Arc<RgbaImage>>,
+    image: RgbaImage,
+    texture: wgpu::Texture,
+    renderer: render::Renderer,
+    colourer: Colourer,
+}
+
+fn model(app: &App) -> Model {
+    app.new_window()
+        .size(config::WIDTH, config::HEIGHT)
+        .view(view)
+        .mouse_wheel(mouse_wheel)
+        .mouse_pressed(mouse_pressed)
+        .mouse_moved(mouse_moved)
+        .mouse_released(mouse_released)
+        .key_pressed(key_pressed)
+        .build()
+        .unwrap();
+
+    let bookmarks = bookmarks::load(&bookmarks::bookmarks_path(app));
+
+    let (target_re, target_im, zoom_limit) = match config::TARGET_BOOKMARK {
+        Some(name) => {
+            let bookmark = bookmarks::find(&bookmarks, name)
+                .unwrap_or_else(|| panic!("no bookmark named `{}`", name));
+            let (re, im) = bookmark_target(bookmark);
+            (re, im, bookmark.scale)
         }
+        None => (
+            Fixed::parse(config::TARGET_RE, config::TARGET_PRECISION_BITS)
+                .expect("invalid `TARGET_RE`"),
+            Fixed::parse(config::TARGET_IM, config::TARGET_PRECISION_BITS)
+                .expect("invalid `TARGET_IM`"),
+            0.,
+        ),
+    };
+
+    let palette = app.assets_path().unwrap().join(config::PALETTE);
+    let gradient = Gradient::load(&palette).unwrap_or_else(|e| panic!("{}", e));
+
+    let camera = config::CAMERA_PATH.map(|path| {
+        CameraPath::load(&app.assets_path().unwrap().join(path)).unwrap_or_else(|e| panic!("{}", e))
+    });
+
+    let trap_image = (config::COLOURING == Colouring::OrbitTrap
+        && config::TRAP == TrapShape::Image)
+        .then(|| {
+            let path = app.assets_path().unwrap().join(config::TRAP_IMAGE);
+            let image =
+                nannou::image::open(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            Arc::new(image.to_rgba8())
+        });
+
+    let texture = wgpu::TextureBuilder::new()
+        .size([config::WIDTH, config::HEIGHT])
+        .format(wgpu::TextureFormat::Rgba8UnormSrgb)
+        .usage(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::TEXTURE_BINDING)
+        .build(app.main_window().device());
+
+    let viewport = Viewport::new(-0.5, 0., START_SCALE);
+    let buddhabrot =
+        config::BUDDHABROT.then(|| Buddhabrot::new(&viewport, buddhabrot::state_path(app)));
+
+    Model {
+        viewport,
+        target_re,
+        target_im,
+        zoom_limit,
+        flying: !config::INTERACTIVE && !config::BUDDHABROT,
+        dirty: true,
+        step: 0,
+        drag: None,
+        bookmarks,
+        buffer: None,
+        camera,
+        buddhabrot,
+        trap_image,
+        image: RgbaImage::new(config::WIDTH, config::HEIGHT),
+        texture,
+        renderer: render::Renderer::new(),
+        colourer: Colourer {
+            colouring: config::COLOURING,
+            gradient,
+            offset: 0.,
+        },
     }
 }
 
-impl Model {
-    fn update(&mut self, x: f32, y: f32, w: f32, h: f32) {
-        self.x += x;
-        self.y += y;
-        self.w = w;
-        self.h = h;
+fn bookmark_target(bookmark: &Bookmark) -> (Fixed, Fixed) {
+    let bits = viewport::precision_for(bookmark.scale).max(config::TARGET_PRECISION_BITS);
+    bookmark
+        .centre(bits)
+        .unwrap_or_else(|| panic!("invalid centre in bookmark `{}`", bookmark.name))
+}
+
+// Powers of ten zoomed in from the starting view
+fn zoom_depth(viewport: &Viewport) -> f64 {
+    (START_SCALE / viewport.scale).log10()
+}
+
+// Supersamples the finished image, unless it's only wanted for captures and
+// nothing is being captured
+fn antialias(app: &App, model: &mut Model) {
+    let capturing = config::CAPTURE_OUTPUT && !config::INTERACTIVE;
+    if config::SUPERSAMPLE_CAPTURE_ONLY && !capturing {
+        return;
     }
 
-    fn update_w_h(&mut self, w: f32, h: f32) {
-        self.w = w;
-        self.h = h;
+    if let Some(buffer) = &model.buffer {
+        supersample::supersample(
+            &mut model.renderer,
+            &model.colourer,
+            buffer,
+            &model.viewport,
+            app.elapsed_frames(),
+            &mut model.image,
+        );
     }
 }
 
-fn model(_app: &App) -> Model {
-    Model {
-        x: 0.,
-        y: 0.,
-        w: 4.,
-        h: 8.,
-    }
+// The configured trap on `frame`, when the colouring uses one
+fn trap(model: &Model, frame: u64, angle: f64, scale: f64) -> Option<Trap> {
+    (config::COLOURING == Colouring::OrbitTrap)
+        .then(|| Trap::from_config(frame, angle, scale, model.trap_image.clone()))
 }
 
-fn update(_app: &App, model: &mut Model, _update: Update) {
-    if (config::TARGET_X - model.x).abs() <= 0.00001
-        && (config::TARGET_Y - model.y).abs() <= 0.00001
-    {
-        model.update_w_h(
-            model.w * config::SCALE_FACTOR,
-            model.h * config::SCALE_FACTOR,
-        );
+fn max_iteration(app: &App, model: &Model) -> i32 {
+    let max_iteration = if config::INTERACTIVE {
+        // Deeper views need longer orbits to show any detail
+        100 + (zoom_depth(&model.viewport).max(0.) * 150.) as u64
     } else {
-        model.update(
-            (config::TARGET_X - model.x) * 0.005,
-            (config::TARGET_Y - model.y) * 0.005,
-            model.w * config::SCALE_FACTOR,
-            model.h * config::SCALE_FACTOR,
+        100 + app.elapsed_frames()
+    };
+
+    max_iteration.min(config::MAX_ITERATION as u64) as i32
+}
+
+fn update(app: &App, model: &mut Model, _update: Update) {
+    if let Some(buddhabrot) = &mut model.buddhabrot {
+        if buddhabrot.viewport() != &model.viewport {
+            buddhabrot.reset(&model.viewport);
+        }
+        buddhabrot.sample();
+        if app
+            .elapsed_frames()
+            .is_multiple_of(config::BUDDHABROT_SAVE_EVERY)
+        {
+            buddhabrot.save();
+        }
+        model.image = buddhabrot.image();
+        return;
+    }
+
+    if let Some(camera) = &model.camera {
+        let shot = camera.at(app.elapsed_frames());
+        model.viewport = shot.viewport;
+        model.colourer.offset = shot.palette_offset;
+        model.renderer.trap = trap(
+            model,
+            app.elapsed_frames(),
+            shot.trap_angle,
+            shot.trap_scale,
         );
+        let buffer =
+            model
+                .renderer
+                .render(&model.viewport, app.elapsed_frames(), shot.max_iteration, 1);
+        model.image = model.colourer.colour(&buffer);
+        model.buffer = Some(buffer);
+        antialias(app, model);
+        return;
+    }
+
+    // Interactive views start coarse so the window keeps up, captures go
+    // straight to full detail
+    if model.dirty {
+        model.step = if config::INTERACTIVE {
+            config::PROGRESSIVE_STEP.max(1)
+        } else {
+            1
+        };
+        model.dirty = false;
+    }
+    // A spinning trap changes every frame even when the view stays put
+    if config::TRAP_SPIN != 0. {
+        model.step = model.step.max(1);
+    }
+    if model.step > 0 {
+        model.renderer.trap = trap(model, app.elapsed_frames(), 0., 1.);
+        let max_iteration = max_iteration(app, model);
+        model.buffer = Some(model.renderer.render(
+            &model.viewport,
+            app.elapsed_frames(),
+            max_iteration,
+            model.step,
+        ));
+        model.step /= 2;
+    }
+
+    model.colourer.offset = app.elapsed_frames() as f32 * config::PALETTE_CYCLE_SPEED;
+    model.image = model.colourer.colour(model.buffer.as_ref().unwrap());
+    if model.step == 0 {
+        antialias(app, model);
+    }
+
+    if model.flying {
+        // Zooms back out too when the target bookmark is shallower than the view
+        if model.viewport.scale * config::SCALE_FACTOR > model.zoom_limit {
+            model.viewport.zoom(config::SCALE_FACTOR);
+        } else if model.viewport.scale < model.zoom_limit * config::SCALE_FACTOR {
+            model.viewport.zoom(1. / config::SCALE_FACTOR);
+        }
+        model
+            .viewport
+            .approach(&model.target_re, &model.target_im, config::APPROACH_RATE);
+        // The view moved, but going back to the coarse pass every frame would
+        // never get past it. Refinement carries on from where it got to.
+        model.step = model.step.max(1);
     }
 }
 
-fn calculate_point(model: &Model, x: i32, y: i32, step: f32, max_iteration: i32) -> i32 {
-    let yp = (model.x - model.w / 2.) + x as f32 * step;
-    let xp = (model.y - model.h / 2.) + y as f32 * step;
+fn mouse_wheel(app: &App, model: &mut Model, delta: MouseScrollDelta, _phase: TouchPhase) {
+    if !config::INTERACTIVE {
+        return;
+    }
+
+    let notches = match delta {
+        MouseScrollDelta::LineDelta(_, y) => y as f64,
+        MouseScrollDelta::PixelDelta(position) => position.y / 50.,
+    };
+    let cursor = app.mouse.position();
+    model.viewport.zoom_about(
+        config::SCROLL_ZOOM.powf(notches),
+        cursor.x as f64,
+        cursor.y as f64,
+    );
+    model.flying = false;
+    model.dirty = true;
+}
 
-    let mut iteration = 0;
-    let mut xi = 0.;
-    let mut yi = 0.;
-    while xi * xi + yi * yi <= 4. && iteration < max_iteration {
-        let xtemp = xi * xi - yi * yi + xp as f64;
-        yi = 2. * xi * yi + yp as f64;
+fn mouse_pressed(app: &App, model: &mut Model, button: MouseButton) {
+    if config::INTERACTIVE && button == MouseButton::Left {
+        model.drag = Some(Drag {
+            last: app.mouse.position(),
+            moved: 0.,
+        });
+    }
+}
 
-        xi = xtemp;
+fn mouse_moved(_app: &App, model: &mut Model, position: Point2) {
+    if let Some(drag) = &mut model.drag {
+        let delta = position - drag.last;
+        let (d_re, d_im) = model.viewport.screen_offset(delta.x as f64, delta.y as f64);
+        model.viewport.pan(-d_re, -d_im);
+        drag.moved += delta.length();
+        drag.last = position;
+        model.flying = false;
+        model.dirty = true;
+    }
+}
 
-        iteration += 1
+fn mouse_released(app: &App, model: &mut Model, button: MouseButton) {
+    if button != MouseButton::Left {
+        return;
     }
 
-    iteration
+    let drag = match model.drag.take() {
+        Some(drag) => drag,
+        None => return,
+    };
+
+    // A click flies towards the point under the cursor
+    if drag.moved < CLICK_DISTANCE {
+        let cursor = app.mouse.position();
+        let (d_re, d_im) = model
+            .viewport
+            .screen_offset(cursor.x as f64, cursor.y as f64);
+        let bits = model.viewport.re.bits();
+        model.target_re = &model.viewport.re + &Fixed::from_f64(d_re, bits);
+        model.target_im = &model.viewport.im + &Fixed::from_f64(d_im, bits);
+        model.zoom_limit = 0.;
+        model.flying = true;
+        model.dirty = true;
+    }
 }
 
-fn calculate_region(
-    model: Model,
-    x0: i32,
-    size: i32,
-    step: f32,
-    h: i32,
-    max_iteration: i32,
-) -> Vec<Vec<i32>> {
-    let mut res = vec![vec![0; h as usize]; size as usize];
-    let mut x = 0;
-    while x < size as i32 {
-        let mut y = 0;
-        while y < h {
-            res[x as usize][y as usize] = calculate_point(&model, x0 + x, y, step, max_iteration);
-            y += 1;
-        }
-        x += 1;
+// Closing the window, keeps the samples since the last periodic save
+fn exit(_app: &App, model: Model) {
+    if let Some(buddhabrot) = &model.buddhabrot {
+        buddhabrot.save();
     }
+}
 
-    res
+fn key_pressed(app: &App, model: &mut Model, key: Key) {
+    if !config::INTERACTIVE {
+        return;
+    }
+
+    let numbers = [
+        Key::Key1,
+        Key::Key2,
+        Key::Key3,
+        Key::Key4,
+        Key::Key5,
+        Key::Key6,
+        Key::Key7,
+        Key::Key8,
+        Key::Key9,
+    ];
+
+    match key {
+        Key::Space => {
+            model.flying = !model.flying;
+            model.dirty |= model.flying;
+        }
+        Key::B => {
+            let name = format!("bookmark-{}", model.bookmarks.len() + 1);
+            warn!("Saved {}", name);
+            model
+                .bookmarks
+                .push(Bookmark::from_viewport(name, &model.viewport));
+            bookmarks::save(&bookmarks::bookmarks_path(app), &model.bookmarks);
+        }
+        _ => {
+            let bookmark = numbers
+                .iter()
+                .position(|&k| k == key)
+                .and_then(|i| model.bookmarks.get(i));
+            if let Some(bookmark) = bookmark {
+                let (re, im) = bookmark_target(bookmark);
+                model.target_re = re;
+                model.target_im = im;
+                model.zoom_limit = bookmark.scale;
+                model.flying = true;
+                model.dirty = true;
+            }
+        }
+    }
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
     let draw = app.draw();
-    let window = app.window_rect();
     let nth = frame.nth();
 
     frame.clear(BLACK);
 
-    let bottom_left = window.bottom_left();
-
-    let x0 = bottom_left.x;
-    let y0 = bottom_left.y;
-    let w = window.w();
-    let h = window.h() as i32;
-
-    let max_iteration = std::cmp::min(100 + nth, 2000) as i32;
-    let step = model.w / w;
-
-    let region_size = config::WIDTH as i32 / config::THREAD_COUNT;
-
-    let threads: Vec<_> = (0..config::THREAD_COUNT)
-        .map(|i| {
-            let temp_model = model.clone();
-            std::thread::spawn(move || {
-                calculate_region(
-                    temp_model,
-                    region_size * i,
-                    region_size,
-                    step,
-                    h,
-                    max_iteration,
-                )
-            })
-        })
-        .collect();
-
-    for (i, thread) in threads.into_iter().enumerate() {
-        let res = thread.join().unwrap();
-
-        let dx = region_size as f32 * i as f32;
-
-        let mut x = 0;
-        while x < region_size {
-            let mut y = 0;
-            while y < h {
-                let v = res[x as usize][y as usize];
-
-                if v == max_iteration {
-                    draw.rect()
-                        .w_h(1., 1.)
-                        .x_y(x as f32 + x0 + dx, y as f32 + y0)
-                        .color(BLACK);
-                } else {
-                    let gray_percentage = v as f32 / max_iteration as f32;
-                    let tetha = map_range(gray_percentage, 0., 1., 0.721, 0.9);
-                    let lightness = map_range(gray_percentage, 0., 1., 0., 0.5);
-                    draw.rect()
-                        .w_h(1., 1.)
-                        .x_y(x as f32 + x0 + dx, y as f32 + y0)
-                        .color(hsl(tetha, 1.0, lightness));
-                }
-
-                y += 1;
-            }
-            x += 1;
-        }
+    {
+        let window = app.main_window();
+        let mut encoder = frame.command_encoder();
+        model
+            .texture
+            .upload_data(window.device(), &mut encoder, model.image.as_raw());
+    }
+
+    draw.texture(&model.texture)
+        .w_h(config::WIDTH as f32, config::HEIGHT as f32);
+
+    if config::SHOW_HUD {
+        let stats = &model.renderer.stats;
+        // Enough digits to tell neighbouring pixels apart
+        let digits = (-model.viewport.scale.log10()).max(0.).ceil() as u32 + 2;
+        let hud = match &model.buddhabrot {
+            Some(buddhabrot) => format!("samples {}", buddhabrot.samples()),
+            None => format!(
+                "re {}\nim {}\ndepth 10^{:.2}\nzoom {:.3e}\niterations {}\nsaved {} ({:.1}%)\nreused {} pixels",
+                model.viewport.re.to_decimal_digits(digits),
+                model.viewport.im.to_decimal_digits(digits),
+                zoom_depth(&model.viewport),
+                model.viewport.scale,
+                stats.iterations,
+                stats.iterations_saved,
+                stats.saved_percentage(),
+                stats.reused
+            ),
+        };
+        let window = app.window_rect();
+        draw.text(&hud)
+            .color(WHITE)
+            .left_justify()
+            .align_text_top()
+            .wh(window.pad(10.).wh())
+            .xy(window.xy());
     }
 
     draw.to_frame(app, &frame).unwrap();
 
-    capture::capture(app, frame);
+    if config::INTERACTIVE {
+        return;
+    }
 
-    warn!("Frame {}", nth);
+    capture::capture(app, nth, &model.image);
+    if let Some(buffer) = &model.buffer {
+        export::export(app, nth, buffer, &model.viewport);
+    }
+
+    warn!("Frame {} zoom {:.3e}", nth, model.viewport.scale);
 
-    if config::CAPTURE_OUTPUT && nth == config::CAPTURE_FRAMES {
+    let last_frame = match &model.camera {
+        Some(camera) => camera.last_frame(),
+        None => config::CAPTURE_FRAMES,
+    };
+    if config::CAPTURE_OUTPUT && nth == last_frame {
+        if let Some(buddhabrot) = &model.buddhabrot {
+            buddhabrot.save();
+        }
         std::process::exit(0);
     }
 }
@@ -0,0 +1,128 @@
+use crate::config;
+use crate::precision::Fixed;
+use crate::render::Sample;
+use crate::trap::{Trap, TrapHit};
+
+// Orbit of the viewport centre, iterated in full precision once per frame and
+// rounded to f64. Every pixel then only iterates its small difference from it.
+pub struct ReferenceOrbit {
+    points: Vec<(f64, f64)>,
+}
+
+impl ReferenceOrbit {
+    pub fn compute(re: &Fixed, im: &Fixed, max_iteration: i32) -> ReferenceOrbit {
+        let bits = re.bits().max(im.bits());
+        let two = Fixed::from_f64(2., bits);
+
+        let mut points = Vec::with_capacity(max_iteration as usize + 1);
+        let mut zr = Fixed::zero(bits);
+        let mut zi = Fixed::zero(bits);
+        points.push((0., 0.));
+
+        for _ in 0..max_iteration {
+            let zr2 = &zr * &zr;
+            let zi2 = &zi * &zi;
+            let zri = &zr * &zi;
+
+            zr = &(&zr2 - &zi2) + re;
+            zi = &(&two * &zri) + im;
+
+            let point = (zr.to_f64(), zi.to_f64());
+            points.push(point);
+
+            // Past this radius the orbit is useless as a reference
+            if point.0 * point.0 + point.1 * point.1 > (config::BAILOUT * config::BAILOUT).max(1e6)
+            {
+                break;
+            }
+        }
+
+        ReferenceOrbit { points }
+    }
+
+    // Escape time of `centre + (dr, di)`, same counting and periodicity check
+    // as the direct loop. Rebases onto the start of the orbit whenever the pixel
+    // gets closer to zero than its delta, or the reference runs out, which
+    // avoids glitches. `derivative` and `trap` as for `kernel::escape_time`.
+    pub fn iterate(
+        &self,
+        dr: f64,
+        di: f64,
+        max_iteration: i32,
+        epsilon: f64,
+        derivative: Option<f64>,
+        trap: Option<&Trap>,
+    ) -> (Sample, i32) {
+        let last = self.points.len() - 1;
+        let bailout = config::BAILOUT * config::BAILOUT;
+
+        let mut m = 0;
+        let mut zr = 0.;
+        let mut zi = 0.;
+        let mut dx = 0.;
+        let mut dy = 0.;
+        let mut hit = TrapHit::default();
+        let mut check_x = 0.;
+        let mut check_y = 0.;
+        let mut period = 0;
+        let mut limit = 1;
+        let mut iteration = 0;
+        while iteration < max_iteration {
+            let (rr, ri) = self.points[m];
+
+            // The derivative follows the full z = Z + dz
+            if let Some(pixel) = derivative {
+                let (xr, xi) = (rr + zr, ri + zi);
+                let dtemp = 2. * (xr * dx - xi * dy) + pixel;
+                dy = 2. * (xr * dy + xi * dx);
+                dx = dtemp;
+            }
+
+            // dz' = 2 Z dz + dz^2 + dc
+            let nzr = 2. * (rr * zr - ri * zi) + zr * zr - zi * zi + dr;
+            let nzi = 2. * (rr * zi + ri * zr) + 2. * zr * zi + di;
+            zr = nzr;
+            zi = nzi;
+            m += 1;
+            iteration += 1;
+
+            let (rr, ri) = self.points[m];
+            let xr = rr + zr;
+            let xi = ri + zi;
+            let norm = xr * xr + xi * xi;
+            if let Some(trap) = trap {
+                trap.track(&mut hit, (xr, xi));
+            }
+            if norm > bailout {
+                let sample = Sample::escaped(iteration, norm, 2.).with_trap(&hit);
+                if derivative.is_some() {
+                    return (sample.with_derivative((xr, xi), (dx, dy)), 0);
+                }
+                return (sample, 0);
+            }
+
+            if (xr - check_x).abs() <= epsilon && (xi - check_y).abs() <= epsilon {
+                return (
+                    Sample::inside(max_iteration).with_trap(&hit),
+                    max_iteration - iteration,
+                );
+            }
+
+            period += 1;
+            if period == limit {
+                check_x = xr;
+                check_y = xi;
+                period = 0;
+                limit *= 2;
+            }
+
+            if norm < zr * zr + zi * zi || m == last {
+                zr = xr;
+                zi = xi;
+                m = 0;
+            }
+        }
+
+        (Sample::inside(max_iteration).with_trap(&hit), 0)
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+type Task = Arc<dyn Fn(usize) + Send + Sync>;
+
+struct State {
+    generation: u64,
+    task: Option<Task>,
+    active: usize,
+    shutdown: bool,
+}
+
+struct Shared {
+    // Each worker drains its own queue front first, then steals from the back of the others
+    queues: Vec<Mutex<VecDeque<usize>>>,
+    state: Mutex<State>,
+    remaining: AtomicUsize,
+    start: Condvar,
+    finished: Condvar,
+    busy_nanos: Vec<AtomicU64>,
+}
+
+impl Shared {
+    fn next_job(&self, worker: usize) -> Option<usize> {
+        if let Some(job) = self.queues[worker].lock().unwrap().pop_front() {
+            return Some(job);
+        }
+
+        let count = self.queues.len();
+        (1..count).find_map(|offset| {
+            self.queues[(worker + offset) % count]
+                .lock()
+                .unwrap()
+                .pop_back()
+        })
+    }
+}
+
+// Threads that live as long as the sketch and get handed a batch of jobs per frame
+pub struct Pool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    pub fn new(thread_count: usize) -> Pool {
+        let thread_count = thread_count.max(1);
+        let shared = Arc::new(Shared {
+            queues: (0..thread_count)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            state: Mutex::new(State {
+                generation: 0,
+                task: None,
+                active: 0,
+                shutdown: false,
+            }),
+            remaining: AtomicUsize::new(0),
+            start: Condvar::new(),
+            finished: Condvar::new(),
+            busy_nanos: (0..thread_count).map(|_| AtomicU64::new(0)).collect(),
+        });
+
+        let workers = (0..thread_count)
+            .map(|i| {
+                let shared = shared.clone();
+                std::thread::spawn(move || work(shared, i))
+            })
+            .collect();
+
+        Pool { shared, workers }
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Runs `job` for every index in `order`, earliest first, and returns the
+    // results indexed by job together with how busy each thread was (0 to 1)
+    pub fn run<T, F>(&self, order: &[usize], job: F) -> (Vec<T>, Vec<f32>)
+    where
+        T: Send + 'static,
+        F: Fn(usize) -> T + Send + Sync + 'static,
+    {
+        let job_count = order.iter().map(|i| i + 1).max().unwrap_or(0);
+        let results: Arc<Vec<Mutex<Option<T>>>> =
+            Arc::new((0..job_count).map(|_| Mutex::new(None)).collect());
+
+        let slots = results.clone();
+        let task: Task = Arc::new(move |i| {
+            let value = job(i);
+            *slots[i].lock().unwrap() = Some(value);
+        });
+
+        // Deal the jobs round robin so every thread starts with the most expensive ones
+        let thread_count = self.thread_count();
+        for (n, i) in order.iter().enumerate() {
+            self.shared.queues[n % thread_count]
+                .lock()
+                .unwrap()
+                .push_back(*i);
+        }
+        for busy in &self.shared.busy_nanos {
+            busy.store(0, Ordering::Relaxed);
+        }
+
+        let started = Instant::now();
+        let mut state = self.shared.state.lock().unwrap();
+        self.shared.remaining.store(order.len(), Ordering::SeqCst);
+        state.task = Some(task);
+        state.generation += 1;
+        self.shared.start.notify_all();
+
+        while self.shared.remaining.load(Ordering::SeqCst) > 0 || state.active > 0 {
+            state = self.shared.finished.wait(state).unwrap();
+        }
+        state.task = None;
+        drop(state);
+
+        let wall = started.elapsed().as_nanos().max(1) as f32;
+        let utilisation = self
+            .shared
+            .busy_nanos
+            .iter()
+            .map(|busy| busy.load(Ordering::Relaxed) as f32 / wall)
+            .collect();
+
+        let results = Arc::try_unwrap(results)
+            .ok()
+            .expect("pool job outlived its frame");
+        let results = results
+            .into_iter()
+            .map(|slot| slot.into_inner().unwrap().expect("pool job did not run"))
+            .collect();
+
+        (results, utilisation)
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.start.notify_all();
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(shared: Arc<Shared>, worker: usize) {
+    let mut seen = 0;
+    loop {
+        let task = {
+            let mut state = shared.state.lock().unwrap();
+            while state.generation == seen && !state.shutdown {
+                state = shared.start.wait(state).unwrap();
+            }
+            if state.shutdown {
+                return;
+            }
+
+            // A worker that wakes up after the batch is already done has nothing to do
+            seen = state.generation;
+            match state.task.clone() {
+                Some(task) => {
+                    state.active += 1;
+                    task
+                }
+                None => continue,
+            }
+        };
+
+        while let Some(job) = shared.next_job(worker) {
+            let started = Instant::now();
+            task(job);
+            shared.busy_nanos[worker]
+                .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            shared.remaining.fetch_sub(1, Ordering::SeqCst);
+        }
+        drop(task);
+
+        let mut state = shared.state.lock().unwrap();
+        state.active -= 1;
+        shared.finished.notify_all();
+    }
+}
@@ -0,0 +1,200 @@
+use std::ops::{Add, Mul, Sub};
+
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+// Fixed point number with `bits` of fraction, used for coordinates that have
+// to stay exact long after f64 runs out of digits (the viewport centre and the
+// reference orbit). Everything per pixel stays in f64 as offsets from it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixed {
+    mantissa: BigInt,
+    bits: u32,
+}
+
+impl Fixed {
+    pub fn zero(bits: u32) -> Fixed {
+        Fixed {
+            mantissa: BigInt::zero(),
+            bits,
+        }
+    }
+
+    pub fn from_f64(value: f64, bits: u32) -> Fixed {
+        if value == 0. || !value.is_finite() {
+            return Fixed::zero(bits);
+        }
+
+        // Split the float into an exact integer mantissa and a power of two
+        let raw = value.to_bits();
+        let exponent = ((raw >> 52) & 0x7ff) as i64;
+        let fraction = raw & 0x000f_ffff_ffff_ffff;
+        let (mantissa, exponent) = if exponent == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | 0x0010_0000_0000_0000, exponent - 1075)
+        };
+
+        let mut mantissa = BigInt::from(mantissa);
+        let shift = exponent + bits as i64;
+        if shift >= 0 {
+            mantissa <<= shift as usize;
+        } else {
+            mantissa >>= (-shift) as usize;
+        }
+
+        if value < 0. {
+            mantissa = -mantissa;
+        }
+
+        Fixed { mantissa, bits }
+    }
+
+    // Parses a plain decimal literal such as "-0.5233332272276596035200"
+    pub fn parse(literal: &str, bits: u32) -> Option<Fixed> {
+        let literal = literal.trim();
+        let (negative, digits) = match literal.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, literal.strip_prefix('+').unwrap_or(literal)),
+        };
+
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty() && fraction.is_empty() {
+            return None;
+        }
+
+        let all_digits = format!("{}{}", integer, fraction);
+        let numerator = BigInt::parse_bytes(all_digits.as_bytes(), 10)?;
+        let denominator = BigInt::from(10).pow(fraction.len() as u32);
+        let mut mantissa = (numerator << bits as usize) / denominator;
+
+        if negative {
+            mantissa = -mantissa;
+        }
+
+        Some(Fixed { mantissa, bits })
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn with_bits(&self, bits: u32) -> Fixed {
+        let mantissa = if bits >= self.bits {
+            &self.mantissa << (bits - self.bits) as usize
+        } else {
+            &self.mantissa >> (self.bits - bits) as usize
+        };
+
+        Fixed { mantissa, bits }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        // Keep only the top 64 significant bits so huge mantissas never
+        // overflow, tiny values keep their digits however deep they are
+        let excess = self.mantissa.bits().saturating_sub(64);
+        let top = (&self.mantissa >> excess as usize).to_f64().unwrap_or(0.);
+        top * 2f64.powi(excess as i32 - self.bits as i32)
+    }
+
+    pub fn mul_f64(&self, value: f64) -> Fixed {
+        self * &Fixed::from_f64(value, self.bits)
+    }
+
+    pub fn abs(&self) -> Fixed {
+        Fixed {
+            mantissa: self.mantissa.abs(),
+            bits: self.bits,
+        }
+    }
+
+    // Decimal representation with enough digits to round-trip through `parse`
+    pub fn to_decimal(&self) -> String {
+        let digits = (self.bits as f64 * std::f64::consts::LOG10_2).ceil() as u32 + 1;
+        self.to_decimal_digits(digits)
+    }
+
+    // Decimal representation cut off after `digits` fraction digits
+    pub fn to_decimal_digits(&self, digits: u32) -> String {
+        let scaled = (self.mantissa.abs() * BigInt::from(10).pow(digits)) >> self.bits as usize;
+        let text = format!("{:0>width$}", scaled, width = digits as usize + 1);
+        let (integer, fraction) = text.split_at(text.len() - digits as usize);
+        let sign = if self.mantissa.is_negative() { "-" } else { "" };
+
+        format!("{}{}.{}", sign, integer, fraction)
+    }
+
+    fn aligned(&self, other: &Fixed) -> (BigInt, BigInt, u32) {
+        let bits = self.bits.max(other.bits);
+        (
+            self.with_bits(bits).mantissa,
+            other.with_bits(bits).mantissa,
+            bits,
+        )
+    }
+}
+
+impl Add for &Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: &Fixed) -> Fixed {
+        let (a, b, bits) = self.aligned(other);
+        Fixed {
+            mantissa: a + b,
+            bits,
+        }
+    }
+}
+
+impl Sub for &Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: &Fixed) -> Fixed {
+        let (a, b, bits) = self.aligned(other);
+        Fixed {
+            mantissa: a - b,
+            bits,
+        }
+    }
+}
+
+impl Mul for &Fixed {
+    type Output = Fixed;
+
+    fn mul(self, other: &Fixed) -> Fixed {
+        let (a, b, bits) = self.aligned(other);
+        Fixed {
+            mantissa: (a * b) >> bits as usize,
+            bits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_f64_round_trips_tiny_values() {
+        for bits in [200, 256, 512] {
+            for value in [1e-30, -1e-30, 1e-45, -3.7e-45, 0.6098524555961744, -2.] {
+                let round_trip = Fixed::from_f64(value, bits).to_f64();
+                assert!(
+                    (round_trip - value).abs() <= value.abs() * 1e-12,
+                    "{} came back as {} at {} bits",
+                    value,
+                    round_trip,
+                    bits
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn to_f64_keeps_small_differences() {
+        let a = Fixed::parse("-0.5233332272276596035200", 256).unwrap();
+        let b = &a + &Fixed::from_f64(1e-40, 256);
+        let difference = (&b - &a).to_f64();
+        assert!((difference - 1e-40).abs() < 1e-50, "{}", difference);
+    }
+}
@@ -0,0 +1,558 @@
+use std::sync::Arc;
+
+use log::warn;
+
+use crate::config;
+use crate::fractal::{self, EscapeTime};
+use crate::kernel::{self, Kernel};
+use crate::perturbation::ReferenceOrbit;
+use crate::pool::Pool;
+use crate::reuse::{self, Previous, Reuse};
+use crate::subdivide;
+use crate::trap::{Trap, TrapHit};
+use crate::viewport::Viewport;
+
+// Result of iterating one point: when it escaped and the normalised iteration
+// count, which is continuous across escape bands. Newton fractals also record
+// the root the point converged to, fractals that track the derivative the
+// distance to the set in pixels and the direction away from it, and orbit
+// traps how close the orbit came.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sample {
+    pub iteration: i32,
+    pub smooth: f32,
+    // |z| once it escaped
+    pub modulus: f32,
+    pub root: Option<u8>,
+    pub distance: Option<f32>,
+    pub normal: (f32, f32),
+    pub trap: Option<f32>,
+    pub trap_colour: Option<[u8; 4]>,
+}
+
+impl Sample {
+    pub fn inside(max_iteration: i32) -> Sample {
+        Sample {
+            iteration: max_iteration,
+            smooth: max_iteration as f32,
+            ..Sample::default()
+        }
+    }
+
+    // `degree` is the power of z in the formula, 2 for Mandelbrot
+    pub fn escaped(iteration: i32, norm: f64, degree: f64) -> Sample {
+        let log_z = norm.max(4.).ln() / 2.;
+        let smooth = iteration as f64 + 1. - (log_z / std::f64::consts::LN_2).ln() / degree.ln();
+
+        Sample {
+            iteration,
+            smooth: smooth as f32,
+            modulus: norm.sqrt() as f32,
+            ..Sample::default()
+        }
+    }
+
+    // Adds the distance estimate |z| ln|z| / |dz|, where `dz` is the
+    // derivative measured in pixels, and the unit normal z / dz
+    pub fn with_derivative(mut self, z: (f64, f64), dz: (f64, f64)) -> Sample {
+        let z_abs = (z.0 * z.0 + z.1 * z.1).sqrt();
+        let dz_abs = (dz.0 * dz.0 + dz.1 * dz.1).sqrt();
+        if dz_abs == 0. || !dz_abs.is_finite() {
+            return self;
+        }
+
+        self.distance = Some((z_abs * z_abs.ln() / dz_abs) as f32);
+
+        // z / dz points the same way as z * conj(dz)
+        let (nx, ny) = (z.0 * dz.0 + z.1 * dz.1, z.1 * dz.0 - z.0 * dz.1);
+        let length = (nx * nx + ny * ny).sqrt().max(f64::MIN_POSITIVE);
+        self.normal = ((nx / length) as f32, (ny / length) as f32);
+
+        self
+    }
+
+    pub fn with_trap(mut self, hit: &TrapHit) -> Sample {
+        if hit.distance.is_finite() {
+            self.trap = Some(hit.distance as f32);
+            self.trap_colour = hit.colour;
+        }
+
+        self
+    }
+}
+
+// Escape times of a whole frame, indexed from the bottom left like the window
+#[derive(Clone)]
+pub struct IterationBuffer {
+    pub width: i32,
+    pub height: i32,
+    pub max_iteration: i32,
+    data: Vec<Sample>,
+}
+
+impl IterationBuffer {
+    pub fn new(width: i32, height: i32, max_iteration: i32) -> IterationBuffer {
+        IterationBuffer {
+            width,
+            height,
+            max_iteration,
+            data: vec![Sample::default(); (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Sample {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    pub fn is_inside(&self, x: i32, y: i32) -> bool {
+        self.get(x, y).iteration >= self.max_iteration
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, value: Sample) {
+        self.data[(y * self.width + x) as usize] = value;
+    }
+}
+
+// How a frame is iterated: plain f64 while the zoom is shallow, perturbation
+// against a full precision reference orbit once it isn't
+#[derive(Clone)]
+enum Iteration {
+    Direct {
+        re: f64,
+        im: f64,
+    },
+    Perturbation {
+        orbit: Arc<ReferenceOrbit>,
+        re: f64,
+        im: f64,
+    },
+}
+
+impl Iteration {
+    fn new(viewport: &Viewport, fractal: &dyn EscapeTime, max_iteration: i32) -> Iteration {
+        if viewport.scale < config::PERTURBATION_SCALE && fractal.supports_perturbation() {
+            Iteration::Perturbation {
+                orbit: Arc::new(ReferenceOrbit::compute(
+                    &viewport.re,
+                    &viewport.im,
+                    max_iteration,
+                )),
+                re: viewport.re.to_f64(),
+                im: viewport.im.to_f64(),
+            }
+        } else {
+            Iteration::Direct {
+                re: viewport.re.to_f64(),
+                im: viewport.im.to_f64(),
+            }
+        }
+    }
+}
+
+const INTERIOR_MARGIN: f64 = 1e-12;
+
+#[derive(Clone, Copy)]
+struct Tile {
+    x0: i32,
+    y0: i32,
+    w: i32,
+    h: i32,
+}
+
+// Samples carried over from the last frame and their drift, per pixel
+type Reused = Vec<Option<(Sample, f32)>>;
+
+// Everything a worker needs to work out pixels of one frame
+struct Job {
+    viewport: Viewport,
+    iteration: Iteration,
+    fractal: Arc<dyn EscapeTime>,
+    reused: Arc<Reused>,
+    max_iteration: i32,
+    // Only every `step`-th pixel each way is computed on a coarse pass
+    step: i32,
+    trap: Option<Trap>,
+}
+
+impl Job {
+    fn is_reused(&self, x: i32, y: i32) -> bool {
+        self.reused[(y * config::WIDTH as i32 + x) as usize].is_some()
+    }
+
+    // Samples for the given pixels, taken from the last frame where possible.
+    // Returns the iterations shortcuts skipped.
+    fn calculate_points(&self, points: &[(i32, i32)], out: &mut [Sample]) -> u64 {
+        let w = config::WIDTH as i32;
+        let h = config::HEIGHT as i32;
+
+        let mut offsets = Vec::with_capacity(points.len());
+        let mut missing = Vec::with_capacity(points.len());
+        for (i, &(x, y)) in points.iter().enumerate() {
+            match self.reused[(y * w + x) as usize] {
+                Some((sample, _)) => out[i] = sample,
+                None => {
+                    offsets.push(self.viewport.pixel_offset(x, y, w, h));
+                    missing.push(i);
+                }
+            }
+        }
+
+        let mut samples = vec![Sample::default(); missing.len()];
+        let saved = self.calculate_offsets(&offsets, &mut samples);
+        for (&i, sample) in missing.iter().zip(samples) {
+            out[i] = sample;
+        }
+
+        saved
+    }
+
+    // Samples at offsets (re, im) from the centre of the view
+    fn calculate_offsets(&self, offsets: &[(f64, f64)], out: &mut [Sample]) -> u64 {
+        let epsilon = self.viewport.scale * config::PERIODICITY_TOLERANCE;
+        let max_iteration = self.max_iteration;
+        let derivative = config::COLOURING
+            .needs_derivative()
+            .then_some(self.viewport.scale);
+
+        let mut saved = 0;
+        match &self.iteration {
+            Iteration::Direct { re, im } => {
+                let xs: Vec<f64> = offsets.iter().map(|(dr, _)| re + dr).collect();
+                let ys: Vec<f64> = offsets.iter().map(|(_, di)| im + di).collect();
+                saved +=
+                    self.fractal
+                        .escape_times(&xs, &ys, max_iteration, epsilon, derivative, out);
+            }
+            Iteration::Perturbation { orbit, re, im } => {
+                for (v, &(dr, di)) in out.iter_mut().zip(offsets) {
+                    // c is only known to f64 here, so stay well clear of the bulb edges
+                    if kernel::in_main_bulbs(re + dr, im + di, INTERIOR_MARGIN) {
+                        *v = Sample::inside(max_iteration);
+                        saved += max_iteration as u64;
+                        continue;
+                    }
+
+                    let (sample, skipped) = orbit.iterate(
+                        dr,
+                        di,
+                        max_iteration,
+                        epsilon,
+                        derivative,
+                        self.trap.as_ref(),
+                    );
+                    *v = sample;
+                    saved += skipped as u64;
+                }
+            }
+        }
+
+        saved
+    }
+}
+
+fn calculate_tile(job: &Job, tile: Tile) -> (Vec<Sample>, u64) {
+    if config::SUBDIVIDE && job.step == 1 && job.fractal.connected() {
+        return subdivide::subdivide_tile(
+            tile.w,
+            tile.h,
+            |points, out| {
+                let points: Vec<(i32, i32)> = points
+                    .iter()
+                    .map(|&(x, y)| (tile.x0 + x, tile.y0 + y))
+                    .collect();
+                job.calculate_points(&points, out)
+            },
+            |x, y| job.is_reused(tile.x0 + x, tile.y0 + y),
+        );
+    }
+
+    let row_length = tile.w as usize;
+    let mut saved = 0;
+    let mut res = vec![Sample::default(); row_length * tile.h as usize];
+    let mut points = Vec::with_capacity(row_length);
+    let mut out = vec![Sample::default(); row_length];
+    for (row, y) in res.chunks_mut(row_length).zip(tile.y0..tile.y0 + tile.h) {
+        if y % job.step != 0 {
+            continue;
+        }
+
+        points.clear();
+        points.extend(
+            (tile.x0..tile.x0 + tile.w)
+                .filter(|x| x % job.step == 0)
+                .map(|x| (x, y)),
+        );
+        let out = &mut out[..points.len()];
+        saved += job.calculate_points(&points, out);
+        for (&(x, _), &sample) in points.iter().zip(out.iter()) {
+            row[(x - tile.x0) as usize] = sample;
+        }
+    }
+
+    (res, saved)
+}
+
+// Splits the frame into small tiles and hands them to a persistent pool, most
+// expensive first going by how many iterations each tile took last frame
+pub struct Renderer {
+    pool: Pool,
+    kernel: Kernel,
+    tiles: Vec<Tile>,
+    costs: Vec<u64>,
+    previous: Option<Previous>,
+    // Orbit trap for this frame, set before rendering when colouring by traps
+    pub trap: Option<Trap>,
+    pub stats: FrameStats,
+}
+
+#[derive(Default)]
+pub struct FrameStats {
+    pub iterations: u64,
+    pub iterations_saved: u64,
+    // Pixels taken from the previous frame
+    pub reused: u64,
+}
+
+pub fn thread_count() -> usize {
+    config::THREAD_COUNT.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    })
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        let w = config::WIDTH as i32;
+        let h = config::HEIGHT as i32;
+        let size = config::TILE_SIZE;
+
+        let mut tiles = vec![];
+        for y0 in (0..h).step_by(size as usize) {
+            for x0 in (0..w).step_by(size as usize) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    w: size.min(w - x0),
+                    h: size.min(h - y0),
+                });
+            }
+        }
+
+        let mut kernel = if config::SIMD {
+            Kernel::detect()
+        } else {
+            Kernel::Scalar
+        };
+        if config::VERIFY_KERNEL && kernel != Kernel::Scalar {
+            let mismatches = kernel::verify(kernel);
+            if mismatches > 0 {
+                warn!(
+                    "Kernel {:?} disagrees with scalar on {} points, falling back to scalar",
+                    kernel, mismatches
+                );
+                kernel = Kernel::Scalar;
+            }
+        }
+
+        Renderer {
+            pool: Pool::new(thread_count()),
+            kernel,
+            costs: vec![0; tiles.len()],
+            previous: None,
+            trap: None,
+            stats: FrameStats::default(),
+            tiles,
+        }
+    }
+
+    // Computes every `step`-th pixel each way and fills the gaps with blocks,
+    // a step of 1 is the full frame
+    pub fn render(
+        &mut self,
+        viewport: &Viewport,
+        frame: u64,
+        max_iteration: i32,
+        step: i32,
+    ) -> IterationBuffer {
+        let w = config::WIDTH as i32;
+        let h = config::HEIGHT as i32;
+
+        let max_drift = match config::REUSE {
+            // A finer pass over the same view always keeps what the coarser ones found
+            _ if self.previous.as_ref().map(|p| &p.viewport) == Some(viewport) => Some(0.),
+            Reuse::Off => None,
+            Reuse::Exact => Some(0.),
+            Reuse::Preview => Some(config::REUSE_DISTANCE),
+        };
+        // Samples carry trap distances, so a trap that moved invalidates them
+        let same_trap = |previous: &Previous| match (&previous.trap, &self.trap) {
+            (Some(a), Some(b)) => a.same_as(b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        let reused = match (&self.previous, max_drift) {
+            (Some(previous), Some(max_drift))
+                if !config::FRACTAL.is_animated() && same_trap(previous) =>
+            {
+                reuse::reproject(previous, viewport, max_iteration, max_drift, w, h)
+            }
+            _ => vec![None; (w * h) as usize],
+        };
+        let reused = Arc::new(reused);
+
+        let (buffer, drift, stats) = self.calculate(viewport, frame, max_iteration, step, &reused);
+
+        warn!(
+            "Iterations {} saved {} ({:.1}%) reused {} pixels",
+            stats.iterations,
+            stats.iterations_saved,
+            stats.saved_percentage(),
+            stats.reused
+        );
+
+        if config::VERIFY_REUSE && stats.reused > 0 {
+            let nothing = Arc::new(vec![None; (w * h) as usize]);
+            let (fresh, _, _) = self.calculate(viewport, frame, max_iteration, step, &nothing);
+            let (mismatches, error) = reuse::compare(&reused, &buffer, &fresh);
+            warn!(
+                "Reuse wrong on {} of {} pixels, smooth count off by {:.4} on average",
+                mismatches, stats.reused, error
+            );
+        }
+
+        self.stats = stats;
+        self.previous = Some(Previous {
+            viewport: viewport.clone(),
+            buffer: buffer.clone(),
+            drift,
+            trap: self.trap.clone(),
+        });
+
+        buffer
+    }
+
+    fn calculate(
+        &mut self,
+        viewport: &Viewport,
+        frame: u64,
+        max_iteration: i32,
+        step: i32,
+        reused: &Arc<Reused>,
+    ) -> (IterationBuffer, Vec<f32>, FrameStats) {
+        let w = config::WIDTH as i32;
+        let h = config::HEIGHT as i32;
+        let step = step.max(1);
+
+        let mut order: Vec<usize> = (0..self.tiles.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.costs[i]));
+
+        let tiles = Arc::new(self.tiles.clone());
+        let job = self.job(viewport, frame, max_iteration, step, reused);
+        let (results, utilisation) = self
+            .pool
+            .run(&order, move |i| calculate_tile(&job, tiles[i]));
+
+        let utilisation: Vec<String> = utilisation
+            .iter()
+            .map(|u| format!("{:.0}%", u * 100.))
+            .collect();
+        warn!(
+            "Threads {} busy {}",
+            self.pool.thread_count(),
+            utilisation.join(" ")
+        );
+
+        let mut buffer = IterationBuffer::new(w, h, max_iteration);
+        let mut drift = vec![0.; (w * h) as usize];
+        let mut stats = FrameStats::default();
+        for (i, (tile, (res, saved))) in self.tiles.iter().zip(results).enumerate() {
+            // Shortcut and reused pixels report the full count but cost next to nothing
+            let mut cost = 0;
+            let mut values = res.into_iter();
+            for y in tile.y0..tile.y0 + tile.h {
+                for x in tile.x0..tile.x0 + tile.w {
+                    let value = values.next().unwrap();
+                    let index = (y * w + x) as usize;
+                    match reused[index] {
+                        Some((_, d)) => {
+                            drift[index] = d;
+                            stats.reused += 1;
+                        }
+                        None => cost += value.iteration as u64,
+                    }
+                    buffer.set(x, y, value);
+                }
+            }
+
+            self.costs[i] = cost - saved;
+            stats.iterations += self.costs[i];
+            stats.iterations_saved += saved;
+        }
+
+        // Gaps left by a coarse pass take the computed pixel below and left of
+        // them, and are never reused by the next pass
+        if step > 1 {
+            for y in 0..h {
+                for x in 0..w {
+                    if x % step != 0 || y % step != 0 {
+                        buffer.set(x, y, buffer.get(x - x % step, y - y % step));
+                        drift[(y * w + x) as usize] = f32::INFINITY;
+                    }
+                }
+            }
+        }
+
+        (buffer, drift, stats)
+    }
+
+    fn job(
+        &self,
+        viewport: &Viewport,
+        frame: u64,
+        max_iteration: i32,
+        step: i32,
+        reused: &Arc<Reused>,
+    ) -> Job {
+        let fractal = fractal::build(config::FRACTAL, self.kernel, frame, self.trap.clone());
+        Job {
+            viewport: viewport.clone(),
+            iteration: Iteration::new(viewport, fractal.as_ref(), max_iteration),
+            fractal,
+            reused: reused.clone(),
+            max_iteration,
+            step,
+            trap: self.trap.clone(),
+        }
+    }
+
+    // Samples at any offsets (re, im) from the centre of the view, such as
+    // points between pixel centres, spread over the pool in chunks
+    pub fn sample_offsets(
+        &mut self,
+        viewport: &Viewport,
+        frame: u64,
+        max_iteration: i32,
+        offsets: Vec<(f64, f64)>,
+    ) -> Vec<Sample> {
+        const CHUNK: usize = 1024;
+
+        let job = self.job(viewport, frame, max_iteration, 1, &Arc::new(vec![]));
+        let offsets = Arc::new(offsets);
+        let chunks: Vec<usize> = (0..offsets.len().div_ceil(CHUNK)).collect();
+        let (results, _) = self.pool.run(&chunks, move |i| {
+            let chunk = &offsets[i * CHUNK..((i + 1) * CHUNK).min(offsets.len())];
+            let mut out = vec![Sample::default(); chunk.len()];
+            job.calculate_offsets(chunk, &mut out);
+            out
+        });
+
+        results.concat()
+    }
+}
+
+impl FrameStats {
+    pub fn saved_percentage(&self) -> f64 {
+        let total = self.iterations + self.iterations_saved;
+        self.iterations_saved as f64 / total.max(1) as f64 * 100.
+    }
+}
@@ -0,0 +1,159 @@
+use crate::render::{IterationBuffer, Sample};
+use crate::trap::Trap;
+use crate::viewport::Viewport;
+
+// A point counts as the same one if it lands this close to a previous pixel
+const EXACT_DISTANCE: f32 = 1e-6;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reuse {
+    // Every pixel from scratch
+    Off,
+    // Only pixels that land on a point computed last frame, which happens
+    // when panning by whole pixels. Same point up to f64 rounding, so the odd
+    // chaotic pixel right on the boundary can still come out differently.
+    Exact,
+    // Also pixels that land near one, up to `REUSE_DISTANCE` pixels of drift
+    // in total. Slightly smeared but much cheaper.
+    Preview,
+}
+
+// Last frame, kept to be mapped onto the next one
+pub struct Previous {
+    pub viewport: Viewport,
+    pub buffer: IterationBuffer,
+    // How far, in pixels, each sample already sits from its pixel
+    pub drift: Vec<f32>,
+    pub trap: Option<Trap>,
+}
+
+// Sample and drift for every pixel of `viewport` that can be taken from
+// `previous`, row by row from the bottom left, None where it has to be computed
+pub fn reproject(
+    previous: &Previous,
+    viewport: &Viewport,
+    max_iteration: i32,
+    max_drift: f32,
+    width: i32,
+    height: i32,
+) -> Vec<Option<(Sample, f32)>> {
+    let max_drift = max_drift.max(EXACT_DISTANCE);
+    let old = &previous.viewport;
+    let buffer = &previous.buffer;
+
+    // Subtracted at full precision first, the difference between the centres
+    // is a few pixels and keeps its digits in f64 at any depth
+    let shift_re = (&viewport.re - &old.re).to_f64();
+    let shift_im = (&viewport.im - &old.im).to_f64();
+
+    let mut reused = vec![None; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let (dr, di) = viewport.pixel_offset(x, y, width, height);
+            let (px, py) = old.screen_position(dr + shift_re, di + shift_im);
+            let px = px + buffer.width as f64 / 2.;
+            let py = py + buffer.height as f64 / 2.;
+
+            let (ox, oy) = (px.round(), py.round());
+            if ox < 0. || oy < 0. || ox >= buffer.width as f64 || oy >= buffer.height as f64 {
+                continue;
+            }
+            let (ox, oy) = (ox as i32, oy as i32);
+
+            let offset = ((px - ox as f64).powi(2) + (py - oy as f64).powi(2)).sqrt() as f32;
+            let drift = previous.drift[(oy * buffer.width + ox) as usize] + offset;
+            if drift > max_drift {
+                continue;
+            }
+
+            // Escaped samples stay right as long as they escaped below the new
+            // limit, inside ones only if the limit hasn't moved
+            let sample = buffer.get(ox, oy);
+            let valid = if buffer.is_inside(ox, oy) {
+                buffer.max_iteration == max_iteration
+            } else {
+                sample.iteration < max_iteration
+            };
+            if valid {
+                reused[(y * width + x) as usize] = Some((sample, drift));
+            }
+        }
+    }
+
+    reused
+}
+
+// Reused pixels that disagree with a fresh render, and the mean difference in
+// smooth iteration count over all of them
+pub fn compare(
+    reused: &[Option<(Sample, f32)>],
+    buffer: &IterationBuffer,
+    fresh: &IterationBuffer,
+) -> (usize, f64) {
+    let mut mismatches = 0;
+    let mut error = 0.;
+    let mut count = 0;
+    for y in 0..buffer.height {
+        for x in 0..buffer.width {
+            if reused[(y * buffer.width + x) as usize].is_none() {
+                continue;
+            }
+
+            let (a, b) = (buffer.get(x, y), fresh.get(x, y));
+            if a.iteration != b.iteration || a.root != b.root {
+                mismatches += 1;
+            }
+            error += (a.smooth - b.smooth).abs() as f64;
+            count += 1;
+        }
+    }
+
+    (mismatches, error / count.max(1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every old pixel numbered, a pan by whole pixels at 1e-30 units a pixel
+    // has to find each new pixel's old number
+    #[test]
+    fn reproject_pans_deep_zooms_by_whole_pixels() {
+        let (width, height) = (16, 16);
+        let scale = 1e-30;
+        let old = Viewport::new(-0.5233332272276596, 0.6098524555961744, scale);
+        let mut buffer = IterationBuffer::new(width, height, 1000);
+        for y in 0..height {
+            for x in 0..width {
+                let sample = Sample {
+                    iteration: y * width + x,
+                    ..Default::default()
+                };
+                buffer.set(x, y, sample);
+            }
+        }
+        let previous = Previous {
+            viewport: old.clone(),
+            buffer,
+            drift: vec![0.; (width * height) as usize],
+            trap: None,
+        };
+
+        let mut viewport = old;
+        viewport.pan(3. * scale, 5. * scale);
+        let reused = reproject(&previous, &viewport, 1000, 0., width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let (ox, oy) = (x + 5, y + 3);
+                let found = reused[(y * width + x) as usize].map(|(sample, _)| sample.iteration);
+                if ox < width && oy < height {
+                    assert_eq!(found, Some(oy * width + ox), "pixel {} {}", x, y);
+                } else {
+                    assert_eq!(found, None, "pixel {} {}", x, y);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::config;
+use crate::render::Sample;
+
+// Mariani-Silver subdivision of one tile. Works out the border of a rectangle
+// and, if every pixel on it escaped at the same count, fills the inside
+// without iterating it: the set is connected, so nothing different can hide
+// in there short of a minibrot smaller than the gaps between border pixels.
+// Otherwise splits the rectangle in four and tries again. Only used for
+// fractals whose `EscapeTime::connected` says so.
+//
+// `compute` works out samples for tile pixels (x, y) and returns the iterations
+// shortcuts saved, `is_reused` tells which pixels came from the last frame.
+pub fn subdivide_tile(
+    width: i32,
+    height: i32,
+    compute: impl Fn(&[(i32, i32)], &mut [Sample]) -> u64,
+    is_reused: impl Fn(i32, i32) -> bool,
+) -> (Vec<Sample>, u64) {
+    let mut tile = Subdivision {
+        width,
+        samples: vec![Sample::default(); (width * height) as usize],
+        known: vec![false; (width * height) as usize],
+        saved: 0,
+        compute,
+        is_reused,
+    };
+    tile.run((0, 0, width - 1, height - 1));
+
+    (tile.samples, tile.saved)
+}
+
+struct Subdivision<C, R> {
+    width: i32,
+    samples: Vec<Sample>,
+    known: Vec<bool>,
+    saved: u64,
+    compute: C,
+    is_reused: R,
+}
+
+impl<C, R> Subdivision<C, R>
+where
+    C: Fn(&[(i32, i32)], &mut [Sample]) -> u64,
+    R: Fn(i32, i32) -> bool,
+{
+    fn index(&self, x: i32, y: i32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    fn get(&self, x: i32, y: i32) -> Sample {
+        self.samples[self.index(x, y)]
+    }
+
+    // Computes whichever of the points aren't known yet
+    fn compute(&mut self, points: impl Iterator<Item = (i32, i32)>) {
+        let mut points: Vec<(i32, i32)> = points
+            .filter(|&(x, y)| !self.known[self.index(x, y)])
+            .collect();
+        // Neighbouring rectangles share edges
+        points.sort_unstable_by_key(|&(x, y)| (y, x));
+        points.dedup();
+        if points.is_empty() {
+            return;
+        }
+
+        let mut out = vec![Sample::default(); points.len()];
+        self.saved += (self.compute)(&points, &mut out);
+        for (&(x, y), sample) in points.iter().zip(out) {
+            let i = self.index(x, y);
+            self.samples[i] = sample;
+            self.known[i] = true;
+        }
+    }
+
+    // Works through the rectangles a level at a time so each level's borders
+    // go to `compute` as one batch, which keeps the SIMD kernel busy.
+    // Corners inclusive.
+    fn run(&mut self, first: (i32, i32, i32, i32)) {
+        let mut level = vec![first];
+        let mut remaining = vec![];
+        while !level.is_empty() {
+            let borders: Vec<(i32, i32)> = level.iter().flat_map(|&r| border(r)).collect();
+            self.compute(borders.into_iter());
+
+            let mut next = vec![];
+            for (x0, y0, x1, y1) in level {
+                if x1 - x0 < config::SUBDIVIDE_MIN || y1 - y0 < config::SUBDIVIDE_MIN {
+                    remaining.extend((y0..=y1).flat_map(|y| (x0..=x1).map(move |x| (x, y))));
+                    continue;
+                }
+
+                // Distances, normals and traps can't be filled in, only the counts
+                let corner = self.get(x0, y0);
+                let uniform = corner.distance.is_none()
+                    && corner.trap.is_none()
+                    && border((x0, y0, x1, y1)).all(|(x, y)| {
+                        let sample = self.get(x, y);
+                        sample.iteration == corner.iteration && sample.root == corner.root
+                    });
+                if uniform {
+                    self.fill(x0, y0, x1, y1);
+                    continue;
+                }
+
+                let xm = (x0 + x1) / 2;
+                let ym = (y0 + y1) / 2;
+                next.extend([
+                    (x0, y0, xm, ym),
+                    (xm, y0, x1, ym),
+                    (x0, ym, xm, y1),
+                    (xm, ym, x1, y1),
+                ]);
+            }
+            level = next;
+        }
+
+        self.compute(remaining.into_iter());
+    }
+
+    // Fills the inside of a rectangle with a uniform border. The iteration
+    // count is the border's, the smooth count and |z| are blended in from the
+    // four sides (a Coons patch) so neither shows flat blocks.
+    fn fill(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) {
+        for y in y0 + 1..y1 {
+            for x in x0 + 1..x1 {
+                let i = self.index(x, y);
+                if self.known[i] {
+                    continue;
+                }
+                if (self.is_reused)(x, y) {
+                    self.compute(std::iter::once((x, y)));
+                    continue;
+                }
+
+                let rect = (x0, y0, x1, y1);
+                let mut sample = self.get(x0, y0);
+                sample.smooth = self.patch(rect, x, y, |s| s.smooth);
+                sample.modulus = self.patch(rect, x, y, |s| s.modulus);
+                self.samples[i] = sample;
+                self.known[i] = true;
+                self.saved += sample.iteration as u64;
+            }
+        }
+    }
+
+    // `value` at (x, y) blended from the rectangle's border
+    fn patch(
+        &self,
+        (x0, y0, x1, y1): (i32, i32, i32, i32),
+        x: i32,
+        y: i32,
+        value: fn(&Sample) -> f32,
+    ) -> f32 {
+        let at = |x, y| value(&self.get(x, y));
+        let u = (x - x0) as f32 / (x1 - x0) as f32;
+        let v = (y - y0) as f32 / (y1 - y0) as f32;
+
+        let sides = (1. - v) * at(x, y0) + v * at(x, y1) + (1. - u) * at(x0, y) + u * at(x1, y);
+        let corners = (1. - u) * (1. - v) * at(x0, y0)
+            + u * (1. - v) * at(x1, y0)
+            + (1. - u) * v * at(x0, y1)
+            + u * v * at(x1, y1);
+
+        sides - corners
+    }
+}
+
+fn border((x0, y0, x1, y1): (i32, i32, i32, i32)) -> impl Iterator<Item = (i32, i32)> {
+    (x0..=x1)
+        .flat_map(move |x| [(x, y0), (x, y1)])
+        .chain((y0 + 1..y1).flat_map(move |y| [(x0, y), (x1, y)]))
+}
@@ -0,0 +1,124 @@
+use log::warn;
+use nannou::image::{Rgba, RgbaImage};
+use nannou::rand::rand::rngs::SmallRng;
+use nannou::rand::rand::{Rng, SeedableRng};
+
+use crate::colouring::Colourer;
+use crate::config;
+use crate::render::{IterationBuffer, Renderer};
+use crate::viewport::Viewport;
+
+const SEED: u64 = 0x6a69_7474_6572;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Supersampling {
+    Off,
+    // `SUPERSAMPLES` squared points on a regular grid in every pixel
+    Grid,
+    // One random point in each cell of that grid, trading aliasing for noise.
+    // Each pixel keeps its points from frame to frame so zooms don't shimmer.
+    Jittered,
+    // Jittered, but only in pixels that differ strongly from a neighbour
+    Adaptive,
+}
+
+// Replaces pixels of `image`, the coloured `buffer`, with the average colour
+// of several points inside them. Colours are averaged as linear light, so thin
+// filaments keep their brightness instead of going dark.
+pub fn supersample(
+    renderer: &mut Renderer,
+    colourer: &Colourer,
+    buffer: &IterationBuffer,
+    viewport: &Viewport,
+    frame: u64,
+    image: &mut RgbaImage,
+) {
+    let w = buffer.width;
+    let h = buffer.height;
+    let pixel = |x: i32, y: i32| *image.get_pixel(x as u32, (h - 1 - y) as u32);
+
+    let pixels: Vec<(i32, i32)> = (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .filter(|&(x, y)| match config::SUPERSAMPLING {
+            Supersampling::Off => false,
+            Supersampling::Grid | Supersampling::Jittered => true,
+            Supersampling::Adaptive => [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                .iter()
+                .map(|(dx, dy)| ((x + dx).clamp(0, w - 1), (y + dy).clamp(0, h - 1)))
+                .any(|(nx, ny)| {
+                    contrast(pixel(x, y), pixel(nx, ny)) > config::SUPERSAMPLE_CONTRAST
+                }),
+        })
+        .collect();
+    if pixels.is_empty() {
+        return;
+    }
+
+    let n = config::SUPERSAMPLES.max(1);
+    let mut points = Vec::with_capacity(pixels.len() * (n * n) as usize);
+    let mut offsets = Vec::with_capacity(points.capacity());
+    for &(x, y) in &pixels {
+        let mut rng = SmallRng::seed_from_u64(SEED ^ ((y as u64) << 32 | x as u64));
+        for i in 0..n {
+            for j in 0..n {
+                let (jx, jy) = match config::SUPERSAMPLING {
+                    Supersampling::Grid => (0.5, 0.5),
+                    _ => (rng.gen::<f64>(), rng.gen::<f64>()),
+                };
+                let dx = x as f64 + (i as f64 + jx) / n as f64 - 0.5 - w as f64 / 2.;
+                let dy = y as f64 + (j as f64 + jy) / n as f64 - 0.5 - h as f64 / 2.;
+                points.push((x, y));
+                offsets.push(viewport.screen_offset(dx, dy));
+            }
+        }
+    }
+
+    let samples = renderer.sample_offsets(viewport, frame, buffer.max_iteration, offsets);
+    let samples: Vec<_> = points.into_iter().zip(samples).collect();
+    let colours = colourer.colour_samples(buffer, &samples);
+
+    let count = (n * n) as usize;
+    for (&(x, y), colours) in pixels.iter().zip(colours.chunks(count)) {
+        let mut sum = [0.; 3];
+        for colour in colours {
+            for (total, &channel) in sum.iter_mut().zip(colour.0.iter()) {
+                *total += to_linear(channel);
+            }
+        }
+        let average = sum.map(|total| to_srgb(total / count as f32));
+        let old = image.get_pixel_mut(x as u32, (h - 1 - y) as u32);
+        *old = Rgba([average[0], average[1], average[2], old[3]]);
+    }
+
+    warn!(
+        "Supersampled {} pixels with {} samples each",
+        pixels.len(),
+        count
+    );
+}
+
+// Largest difference in any channel, in linear light from 0 to 1
+fn contrast(a: Rgba<u8>, b: Rgba<u8>) -> f32 {
+    (0..3)
+        .map(|i| (to_linear(a[i]) - to_linear(b[i])).abs())
+        .fold(0., f32::max)
+}
+
+fn to_linear(channel: u8) -> f32 {
+    let c = channel as f32 / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_srgb(linear: f32) -> u8 {
+    let c = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    };
+    (c.clamp(0., 1.) * 255.).round() as u8
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use nannou::image::RgbaImage;
+
+use crate::config;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrapShape {
+    Point,
+    // Through the centre at the trap's angle
+    Line,
+    // Two lines crossing at the centre
+    Cross,
+    // Radius is the trap size
+    Circle,
+    // `TRAP_IMAGE` laid on the plane, the trap size wide
+    Image,
+}
+
+// Something in the plane the orbit can come close to. The closest approach
+// over the whole orbit colours the pixel.
+#[derive(Clone)]
+pub struct Trap {
+    pub shape: TrapShape,
+    pub centre: (f64, f64),
+    // Radians
+    pub angle: f64,
+    pub size: f64,
+    pub image: Option<Arc<RgbaImage>>,
+}
+
+// Closest approach so far, and for image traps the colour under it
+#[derive(Clone, Copy, Debug)]
+pub struct TrapHit {
+    pub distance: f64,
+    pub colour: Option<[u8; 4]>,
+}
+
+impl Default for TrapHit {
+    fn default() -> TrapHit {
+        TrapHit {
+            distance: f64::INFINITY,
+            colour: None,
+        }
+    }
+}
+
+impl Trap {
+    // The configured trap on `frame`, turned a further `angle` degrees and
+    // scaled by `scale`
+    pub fn from_config(frame: u64, angle: f64, scale: f64, image: Option<Arc<RgbaImage>>) -> Trap {
+        let angle = config::TRAP_ANGLE + angle + frame as f64 * config::TRAP_SPIN;
+        Trap {
+            shape: config::TRAP,
+            centre: config::TRAP_CENTRE,
+            angle: angle.to_radians(),
+            size: config::TRAP_SIZE * scale,
+            image,
+        }
+    }
+
+    // Same trap apart from the image, which never changes during a run
+    pub fn same_as(&self, other: &Trap) -> bool {
+        self.shape == other.shape
+            && self.centre == other.centre
+            && self.angle == other.angle
+            && self.size == other.size
+    }
+
+    pub fn track(&self, hit: &mut TrapHit, z: (f64, f64)) {
+        // z relative to the trap, turned so the trap's axes line up with x and y
+        let (sin, cos) = self.angle.sin_cos();
+        let (dx, dy) = (z.0 - self.centre.0, z.1 - self.centre.1);
+        let (x, y) = (dx * cos + dy * sin, dy * cos - dx * sin);
+
+        let distance = match self.shape {
+            TrapShape::Point => (x * x + y * y).sqrt(),
+            TrapShape::Line => y.abs(),
+            TrapShape::Cross => x.abs().min(y.abs()),
+            TrapShape::Circle => ((x * x + y * y).sqrt() - self.size).abs(),
+            TrapShape::Image => {
+                let colour = match self.sample_image(x, y) {
+                    Some(colour) => colour,
+                    None => return,
+                };
+                let distance = (x * x + y * y).sqrt();
+                if distance < hit.distance {
+                    hit.colour = Some(colour);
+                }
+                distance
+            }
+        };
+
+        hit.distance = hit.distance.min(distance);
+    }
+
+    // Colour of the image at (x, y) if that's on an opaque part of it
+    fn sample_image(&self, x: f64, y: f64) -> Option<[u8; 4]> {
+        let image = self.image.as_ref()?;
+        let (width, height) = image.dimensions();
+        let u = x / self.size + 0.5;
+        let v = 0.5 - y / self.size * width as f64 / height as f64;
+        if !(0. ..1.).contains(&u) || !(0. ..1.).contains(&v) {
+            return None;
+        }
+
+        let pixel = image.get_pixel((u * width as f64) as u32, (v * height as f64) as u32);
+        if pixel[3] == 0 {
+            return None;
+        }
+
+        Some(pixel.0)
+    }
+}
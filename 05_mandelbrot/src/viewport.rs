@@ -0,0 +1,104 @@
+use crate::config;
+use crate::precision::Fixed;
+
+// Units per pixel of the starting view, the whole set across the window
+pub const START_SCALE: f64 = 4. / config::WIDTH as f64;
+
+// Extra fraction bits kept beyond the size of a pixel
+const GUARD_BITS: u32 = 64;
+
+// The sketch is drawn on its side: the real axis runs up the screen and the
+// imaginary axis runs across it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub re: Fixed,
+    pub im: Fixed,
+    // Complex plane units per pixel
+    pub scale: f64,
+    // Radians, anticlockwise on screen
+    pub rotation: f64,
+}
+
+impl Viewport {
+    pub fn new(re: f64, im: f64, scale: f64) -> Viewport {
+        let bits = precision_for(scale);
+        Viewport {
+            re: Fixed::from_f64(re, bits),
+            im: Fixed::from_f64(im, bits),
+            scale,
+            rotation: 0.,
+        }
+    }
+
+    pub fn precision_bits(&self) -> u32 {
+        precision_for(self.scale)
+    }
+
+    // Offset of a pixel from the centre, (re, im), small enough for f64 at any depth
+    pub fn pixel_offset(&self, x: i32, y: i32, width: i32, height: i32) -> (f64, f64) {
+        let dx = x as f64 - width as f64 / 2.;
+        let dy = y as f64 - height as f64 / 2.;
+
+        self.screen_offset(dx, dy)
+    }
+
+    // Same for a point given in window coordinates, relative to the centre
+    pub fn screen_offset(&self, dx: f64, dy: f64) -> (f64, f64) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let x = dx * cos - dy * sin;
+        let y = dx * sin + dy * cos;
+
+        (y * self.scale, x * self.scale)
+    }
+
+    // Inverse of `screen_offset`, where an offset from the centre lands on screen
+    pub fn screen_position(&self, d_re: f64, d_im: f64) -> (f64, f64) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let x = d_im / self.scale;
+        let y = d_re / self.scale;
+
+        (x * cos + y * sin, y * cos - x * sin)
+    }
+
+    pub fn pan(&mut self, d_re: f64, d_im: f64) {
+        let bits = self.re.bits();
+        self.re = &self.re + &Fixed::from_f64(d_re, bits);
+        self.im = &self.im + &Fixed::from_f64(d_im, bits);
+    }
+
+    // Zooms keeping the point under (dx, dy) where it is on screen
+    pub fn zoom_about(&mut self, factor: f64, dx: f64, dy: f64) {
+        let (d_re, d_im) = self.screen_offset(dx, dy);
+        self.zoom(factor);
+        self.pan(d_re * (1. - factor), d_im * (1. - factor));
+    }
+
+    pub fn zoom(&mut self, factor: f64) {
+        self.scale *= factor;
+
+        let bits = self.precision_bits();
+        if bits > self.re.bits() {
+            self.re = self.re.with_bits(bits);
+            self.im = self.im.with_bits(bits);
+        }
+    }
+
+    // Moves the centre a fraction of the way towards the target, snapping onto
+    // it once the remaining distance is below half a pixel
+    pub fn approach(&mut self, re: &Fixed, im: &Fixed, rate: f64) {
+        let d_re = re - &self.re;
+        let d_im = im - &self.im;
+
+        if d_re.to_f64().abs() < self.scale / 2. && d_im.to_f64().abs() < self.scale / 2. {
+            self.re = re.with_bits(self.re.bits());
+            self.im = im.with_bits(self.im.bits());
+        } else {
+            self.re = &self.re + &d_re.mul_f64(rate);
+            self.im = &self.im + &d_im.mul_f64(rate);
+        }
+    }
+}
+
+pub fn precision_for(scale: f64) -> u32 {
+    (-scale.log2()).max(0.).ceil() as u32 + GUARD_BITS
+}